@@ -0,0 +1,499 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::api;
+use crate::client::{self, Client, Error, Result};
+use crate::provider::{BoxFuture, Kind, Provider};
+
+// Anthropic's Messages API differs from OpenAI's chat completions API in the shape of its requests
+// and replies, so they're translated to and from the OpenAI format the chat history is kept in:
+// - The system prompt, and the summary of older turns, are sent in the `system` field rather than
+//   as messages.
+// - The content of a message is a list of blocks. Tool calls are `tool_use` blocks of the
+//   assistant's message, and their results are `tool_result` blocks of the next user message.
+//   Consecutive messages from the same role, such as attached files and the prompt, are sent as one
+//   message.
+// - `max_tokens` is required, and `presence_penalty`, `frequency_penalty`, `logit_bias` and `n`
+//   aren't supported. Several alternative replies are generated by sending a request for each.
+// - The tokens used are reported as input and output tokens, counting cached input separately.
+// https://docs.anthropic.com/en/api/messages
+
+pub const DEFAULT_BASE_URL: &str = "https://api.anthropic.com/v1";
+// The path of the messages endpoint, relative to the base URL.
+const MESSAGES_PATH: &str = "/messages";
+// The version of the API the requests are written for.
+const API_VERSION: &str = "2023-06-01";
+
+// The most tokens generated for each reply when `max_tokens` isn't set.
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+// The start of the message of the error for a prompt that doesn't fit the context window, which is
+// reported as an invalid request.
+const PROMPT_TOO_LONG: &str = "prompt is too long";
+
+// Anthropic sends requests to Anthropic's API, authenticated by an `x-api-key` header.
+pub struct Anthropic {
+    client: Client,
+    api_key: String,
+}
+
+impl Anthropic {
+    pub fn new(client: Client, api_key: String) -> Self {
+        Self { client, api_key }
+    }
+
+    // Post the request to the messages endpoint.
+    async fn post(&self, request: &Request) -> Result<reqwest::Response> {
+        let headers = [
+            ("x-api-key", self.api_key.clone()),
+            ("anthropic-version", API_VERSION.to_string()),
+        ];
+        self.client
+            .post(&self.client.url(MESSAGES_PATH), &headers, request)
+            .await
+            .map_err(context_length)
+    }
+
+    // Send the chat history and return the reply and the tokens used.
+    async fn send_one(
+        &self,
+        messages: &[api::Message],
+        tools: &[api::Tool],
+    ) -> Result<(api::Message, api::Usage)> {
+        let resp = self
+            .post(&request(self.client.options(), messages, false, tools))
+            .await?;
+        let r: Response = client::read_json(resp).await?;
+        let mut text = String::new();
+        let mut tool_calls = vec![];
+        for block in r.content {
+            match block {
+                Block::Text { text: fragment } => text.push_str(&fragment),
+                Block::ToolUse { id, name, input } => {
+                    tool_calls.push(tool_call(id, name, input.to_string()))
+                }
+                _ => {}
+            }
+        }
+        Ok((reply(text, tool_calls), r.usage.into()))
+    }
+}
+
+impl Provider for Anthropic {
+    fn kind(&self) -> Kind {
+        Kind::Anthropic
+    }
+
+    fn client(&self) -> &Client {
+        &self.client
+    }
+
+    fn client_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    fn send_choices<'a>(
+        &'a self,
+        messages: &'a [api::Message],
+        n: u32,
+        tools: &'a [api::Tool],
+    ) -> BoxFuture<'a, Result<(Vec<api::Message>, api::Usage)>> {
+        Box::pin(async move {
+            let mut replies = vec![];
+            let mut usage = api::Usage {
+                prompt_tokens: 0,
+                completion_tokens: 0,
+                total_tokens: 0,
+            };
+            for _ in 0..n.max(1) {
+                let (reply, reply_usage) = self.send_one(messages, tools).await?;
+                replies.push(reply);
+                usage.prompt_tokens += reply_usage.prompt_tokens;
+                usage.completion_tokens += reply_usage.completion_tokens;
+                usage.total_tokens += reply_usage.total_tokens;
+            }
+            Ok((replies, usage))
+        })
+    }
+
+    fn send_streaming<'a>(
+        &'a self,
+        messages: &'a [api::Message],
+        tools: &'a [api::Tool],
+        on_delta: &'a mut (dyn FnMut(&str) + Send),
+    ) -> BoxFuture<'a, Result<(api::Message, Option<api::Usage>)>> {
+        Box::pin(async move {
+            let resp = self
+                .post(&request(self.client.options(), messages, true, tools))
+                .await?;
+            let mut reply = StreamedReply::default();
+            client::read_events(resp, |data| reply.push(data, on_delta))
+                .await
+                .map_err(context_length)?;
+            Ok(reply.finish())
+        })
+    }
+}
+
+// Report a prompt too long for the context window as such.
+fn context_length(error: Error) -> Error {
+    match error {
+        Error::InvalidRequest { error, .. } if error.message.starts_with(PROMPT_TOO_LONG) => {
+            Error::ContextLengthExceeded { error }
+        }
+        error => error,
+    }
+}
+
+// Build the request for the given chat history, offering the model the given tools.
+fn request(
+    options: &client::Options,
+    messages: &[api::Message],
+    stream: bool,
+    tools: &[api::Tool],
+) -> Request {
+    let unsupported = [
+        ("presence_penalty", options.presence_penalty.is_some()),
+        ("frequency_penalty", options.frequency_penalty.is_some()),
+        ("logit_bias", options.logit_bias.is_some()),
+    ];
+    for (name, _) in unsupported.iter().filter(|(_, set)| *set) {
+        warn!(
+            "{} isn't supported by Anthropic's API, so it isn't sent",
+            name
+        );
+    }
+
+    let system: Vec<_> = messages
+        .iter()
+        .filter(|m| m.role == "system")
+        .filter_map(|m| m.text())
+        .collect();
+    let mut turns: Vec<Message> = vec![];
+    for message in messages.iter().filter(|m| m.role != "system") {
+        let (role, content) = match message.role.as_str() {
+            "tool" => (
+                "user",
+                vec![Block::ToolResult {
+                    tool_use_id: message.tool_call_id.clone().unwrap_or_default(),
+                    content: message.text().unwrap_or_default().into_owned(),
+                }],
+            ),
+            role => (role, blocks(message)),
+        };
+        match turns.last_mut() {
+            Some(last) if last.role == role => last.content.extend(content),
+            _ => turns.push(Message {
+                role: role.to_string(),
+                content,
+            }),
+        }
+    }
+
+    Request {
+        model: options.model.clone(),
+        max_tokens: options.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        system: (!system.is_empty()).then(|| system.join("\n\n")),
+        messages: turns,
+        temperature: options.temperature,
+        top_p: options.top_p,
+        stop_sequences: options.stop.clone(),
+        stream: stream.then_some(true),
+        tools: (!tools.is_empty()).then(|| {
+            tools
+                .iter()
+                .map(|tool| Tool {
+                    name: tool.function.name.clone(),
+                    description: tool.function.description.clone(),
+                    input_schema: tool.function.parameters.clone(),
+                })
+                .collect()
+        }),
+        metadata: options.user.clone().map(|user_id| Metadata { user_id }),
+    }
+}
+
+// The content blocks of a user or assistant message.
+fn blocks(message: &api::Message) -> Vec<Block> {
+    let mut blocks = match &message.content {
+        Some(api::Content::Text(text)) => vec![Block::Text { text: text.clone() }],
+        Some(api::Content::Parts(parts)) => parts
+            .iter()
+            .map(|part| match part {
+                api::ContentPart::Text { text } => Block::Text { text: text.clone() },
+                api::ContentPart::ImageUrl { image_url } => Block::Image {
+                    source: image_source(&image_url.url),
+                },
+            })
+            .collect(),
+        None => vec![],
+    };
+    // The API rejects empty text blocks.
+    blocks.retain(|block| !matches!(block, Block::Text { text } if text.is_empty()));
+    for call in message.tool_calls.iter().flatten() {
+        blocks.push(Block::ToolUse {
+            id: call.id.clone(),
+            name: call.function.name.clone(),
+            // The arguments were written by the model as the input of an earlier reply.
+            input: serde_json::from_str(&call.function.arguments)
+                .unwrap_or_else(|_| Value::Object(Default::default())),
+        });
+    }
+    blocks
+}
+
+// The source of an image given by URL. Images in `data:` URLs are sent as base64 data.
+fn image_source(url: &str) -> ImageSource {
+    match url
+        .strip_prefix("data:")
+        .and_then(|url| url.split_once(";base64,"))
+    {
+        Some((media_type, data)) => ImageSource::Base64 {
+            media_type: media_type.to_string(),
+            data: data.to_string(),
+        },
+        None => ImageSource::Url {
+            url: url.to_string(),
+        },
+    }
+}
+
+fn tool_call(id: String, name: String, arguments: String) -> api::ToolCall {
+    api::ToolCall {
+        id,
+        kind: "function".to_string(),
+        function: api::FunctionCall { name, arguments },
+    }
+}
+
+// The reply message with the given text and tool calls. A reply that only calls tools has no text.
+fn reply(text: String, tool_calls: Vec<api::ToolCall>) -> api::Message {
+    api::Message {
+        role: "assistant".to_string(),
+        content: (tool_calls.is_empty() || !text.is_empty()).then_some(text.into()),
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        tool_call_id: None,
+    }
+}
+
+// StreamedReply assembles a reply from the events of a streamed response.
+#[derive(Default)]
+struct StreamedReply {
+    text: String,
+    tool_calls: Vec<api::ToolCall>,
+    // The index in `tool_calls` of the call started by each `tool_use` block, by block index.
+    tool_call_blocks: HashMap<usize, usize>,
+    usage: Usage,
+}
+
+impl StreamedReply {
+    // Add the event carried by an event's data, calling `on_delta` with any text it adds. Return
+    // whether the event ends the stream.
+    fn push(&mut self, data: &str, on_delta: &mut (dyn FnMut(&str) + Send)) -> Result<bool> {
+        let event: Event = serde_json::from_str(data)
+            .map_err(|e| Error::Response(format!("error decoding response event: {}", e)))?;
+        match event {
+            Event::MessageStart { message } => self.usage = message.usage,
+            Event::ContentBlockStart {
+                index,
+                content_block,
+            } => match content_block {
+                Block::Text { text } => {
+                    on_delta(&text);
+                    self.text.push_str(&text);
+                }
+                // The input arrives in the deltas that follow.
+                Block::ToolUse { id, name, .. } => {
+                    self.tool_call_blocks.insert(index, self.tool_calls.len());
+                    self.tool_calls.push(tool_call(id, name, String::new()));
+                }
+                _ => {}
+            },
+            Event::ContentBlockDelta { index, delta } => match delta {
+                Delta::Text { text } => {
+                    on_delta(&text);
+                    self.text.push_str(&text);
+                }
+                Delta::InputJson { partial_json } => {
+                    if let Some(&i) = self.tool_call_blocks.get(&index) {
+                        self.tool_calls[i]
+                            .function
+                            .arguments
+                            .push_str(&partial_json);
+                    }
+                }
+                Delta::Other => {}
+            },
+            Event::MessageDelta { usage } => self.usage.output_tokens = usage.output_tokens,
+            Event::MessageStop => return Ok(true),
+            // The API reports errors that happen after the stream has started as an event.
+            Event::Error {} => return Err(Error::from_stream_event(data)),
+            Event::Other => {}
+        }
+        Ok(false)
+    }
+
+    // The assembled reply message and the tokens used.
+    fn finish(mut self) -> (api::Message, Option<api::Usage>) {
+        // A tool called without arguments has no input deltas.
+        for call in &mut self.tool_calls {
+            if call.function.arguments.is_empty() {
+                call.function.arguments = "{}".to_string();
+            }
+        }
+        (reply(self.text, self.tool_calls), Some(self.usage.into()))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Request {
+    model: String,
+    // The most tokens to generate. Required.
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<Metadata>,
+}
+
+#[derive(Debug, Serialize)]
+struct Message {
+    // 'user' or 'assistant'.
+    role: String,
+    content: Vec<Block>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Block {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+    },
+    // Blocks this client doesn't send or read, such as the model's thinking.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+#[derive(Debug, Serialize)]
+struct Tool {
+    name: String,
+    description: String,
+    // The parameters the tool accepts, described as a JSON Schema object.
+    input_schema: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct Metadata {
+    // An identifier of the end-user, to help the API detect abuse.
+    user_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    content: Vec<Block>,
+    usage: Usage,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Usage {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    output_tokens: u32,
+    // The input tokens written to and read from the prompt cache, which aren't counted in
+    // `input_tokens`.
+    #[serde(default)]
+    cache_creation_input_tokens: Option<u32>,
+    #[serde(default)]
+    cache_read_input_tokens: Option<u32>,
+}
+
+impl From<Usage> for api::Usage {
+    fn from(usage: Usage) -> Self {
+        let prompt_tokens = usage.input_tokens
+            + usage.cache_creation_input_tokens.unwrap_or(0)
+            + usage.cache_read_input_tokens.unwrap_or(0);
+        api::Usage {
+            prompt_tokens,
+            completion_tokens: usage.output_tokens,
+            total_tokens: prompt_tokens + usage.output_tokens,
+        }
+    }
+}
+
+// The events of a streamed response.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event {
+    // The start of the reply, giving the input tokens.
+    MessageStart {
+        message: MessageStart,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: Block,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: Delta,
+    },
+    // The end of the reply, giving the output tokens so far.
+    MessageDelta {
+        usage: Usage,
+    },
+    MessageStop,
+    // The error is read from the event's data by `Error::from_stream_event`.
+    Error {},
+    // Pings, the ends of blocks and events this client doesn't read.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageStart {
+    usage: Usage,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum Delta {
+    #[serde(rename = "text_delta")]
+    Text { text: String },
+    // The next fragment of a tool call's JSON input.
+    #[serde(rename = "input_json_delta")]
+    InputJson { partial_json: String },
+    #[serde(other)]
+    Other,
+}
@@ -1,4 +1,7 @@
+// The structs below mirror the API's documented fields, including ones this client doesn't read.
+
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 // https://platform.openai.com/docs/api-reference/completions/create
@@ -18,7 +21,7 @@ pub struct Choice {
     pub finish_reason: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct Usage {
     //  The number of tokens in the prompt.
     pub prompt_tokens: u32,
@@ -45,12 +48,210 @@ pub struct ChatResponse {
     pub choices: Vec<Choice>,
 }
 
+// The streamed form of a response, sent as a sequence of 'chat.completion.chunk' objects when the
+// request sets `stream`. Each chunk carries a delta of the message rather than the full message.
+#[derive(Debug, Deserialize)]
+pub struct ChatCompletionChunk {
+    // The ID of the response. Each chunk has the same ID.
+    pub id: String,
+    // The type of the chunk, always 'chat.completion.chunk'.
+    pub object: String,
+    // The creation time of the response, in Unix time. Each chunk has the same timestamp.
+    pub created: u32,
+    // The ID of the model used to generate the response.
+    pub model: String,
+    // The message deltas. Empty in the final usage chunk.
+    pub choices: Vec<ChunkChoice>,
+    // The tokens used counts. Only present in the final chunk, and only if the request's
+    // `stream_options` asked for it.
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChunkChoice {
+    // The index number of the choice in the list of choices.
+    pub index: u32,
+    // The part of the message generated since the previous chunk.
+    pub delta: Delta,
+    // The reason the model stopped generating. See `Choice`. Null until the final chunk for the
+    // choice.
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Delta {
+    // The role of the message. Only sent in the first chunk.
+    pub role: Option<String>,
+    // The next fragment of the message's text.
+    pub content: Option<String>,
+    // The next fragments of the tool calls the model is making.
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+// The part of a tool call generated since the previous chunk. The ID, type and function name are
+// sent in the call's first chunk, and the arguments are spread across the chunks.
+#[derive(Debug, Deserialize)]
+pub struct ToolCallDelta {
+    // The index of the tool call in the message's list of tool calls.
+    pub index: u32,
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FunctionCallDelta {
+    pub name: Option<String>,
+    // The next fragment of the JSON arguments.
+    pub arguments: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Message {
-    // The role of the message. The possible values are: 'user' and 'system', and 'assistant'.
+    // The role of the message. The possible values are: 'user' and 'system', 'assistant' and
+    // 'tool'.
     pub role: String,
-    // The text of the message.
-    pub content: Option<String>,
+    // The content of the message: its text, or for user messages, parts of text and images. Null
+    // for an assistant message that only calls tools.
+    pub content: Option<Content>,
+    // The tools an assistant message calls. The results are sent back as 'tool' messages, one for
+    // each call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    // The call that a 'tool' message gives the result of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl Message {
+    // Create a message with the given role and content.
+    pub fn new(role: &str, content: impl Into<Content>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: Some(content.into()),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    // Create a message giving the result of a tool call.
+    pub fn tool_result(call_id: &str, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(call_id.to_string()),
+            ..Self::new("tool", content.into())
+        }
+    }
+
+    // The text of the message, if it has content. See `Content::text`.
+    pub fn text(&self) -> Option<Cow<'_, str>> {
+        self.content.as_ref().map(Content::text)
+    }
+}
+
+// The content of a message: either plain text, or an array of parts that can mix text and images
+// for vision-capable models.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl Content {
+    // The content if it's plain text.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Content::Text(text) => Some(text),
+            Content::Parts(_) => None,
+        }
+    }
+
+    // The text of the content. The text parts of content with images are joined by blank lines,
+    // leaving out the images.
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            Content::Text(text) => Cow::Borrowed(text),
+            Content::Parts(parts) => Cow::Owned(
+                parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ContentPart::Text { text } => Some(text.as_str()),
+                        ContentPart::ImageUrl { .. } => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n\n"),
+            ),
+        }
+    }
+}
+
+impl From<String> for Content {
+    fn from(text: String) -> Self {
+        Content::Text(text)
+    }
+}
+
+impl From<&str> for Content {
+    fn from(text: &str) -> Self {
+        Content::Text(text.to_string())
+    }
+}
+
+// A part of a message's content.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ImageUrl {
+    // The URL of the image, or the image itself as a base64 `data:` URL.
+    pub url: String,
+    // The resolution the model sees the image at: 'low', 'high' or 'auto', the default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+// A call of a tool by the model.
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct ToolCall {
+    // The ID of the call, which the result refers to.
+    pub id: String,
+    // The type of the tool. Currently, only 'function' is supported.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct FunctionCall {
+    // The name of the function to call.
+    pub name: String,
+    // The arguments to call the function with, as generated by the model in JSON format. The
+    // model may generate invalid JSON or make up parameters, so validate them before calling.
+    pub arguments: String,
+}
+
+// A tool the model may call.
+#[derive(Debug, Serialize, Clone)]
+pub struct Tool {
+    // The type of the tool. Currently, only 'function' is supported.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FunctionDefinition {
+    // The name of the function, of letters, digits, underscores and dashes.
+    pub name: String,
+    // What the function does, used by the model to choose when and how to call it.
+    pub description: String,
+    // The parameters the function accepts, described as a JSON Schema object.
+    pub parameters: serde_json::Value,
 }
 
 #[derive(Debug, Default, serde::Serialize)]
@@ -74,9 +275,9 @@ pub struct ChatRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f64>,
 
-    // How many chat completion choices to generate for each input message. Defaults to 1.0
+    // How many chat completion choices to generate for each input message. Defaults to 1.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub n: Option<f64>,
+    pub n: Option<u32>,
 
     // If set, partial message deltas will be sent, like in ChatGPT. Tokens will be sent as
     // data-only server-sent events as they become available, with the stream terminated by a data:
@@ -84,6 +285,10 @@ pub struct ChatRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
 
+    // Options for streaming responses. Only set this when `stream` is true.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+
     // Up to 4 sequences where the API will stop generating further tokens.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stop: Option<Vec<String>>,
@@ -118,4 +323,46 @@ pub struct ChatRequest {
     // abuse.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+
+    // A list of tools the model may call. Currently, only functions are supported as a tool.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+
+    // Controls which (if any) tool is called by the model: 'none', 'auto' (the default when tools
+    // are present), 'required', or an object naming a particular function.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+
+    // Whether the model may call several tools in one reply. Defaults to true.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct StreamOptions {
+    // If set, an additional chunk will be streamed before the data: [DONE] message. Its `usage`
+    // field shows the token usage statistics for the entire request and its `choices` field is
+    // empty.
+    pub include_usage: bool,
+}
+
+// The body of an unsuccessful response.
+// https://platform.openai.com/docs/guides/error-codes/api-errors
+#[derive(Debug, Deserialize)]
+pub struct ErrorResponse {
+    pub error: ApiError,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiError {
+    // A human-readable description of the error.
+    pub message: String,
+    // The category of the error, such as 'invalid_request_error' or 'insufficient_quota'.
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    // The request parameter that caused the error, if any.
+    pub param: Option<String>,
+    // A machine-readable code for the error, such as 'context_length_exceeded' or
+    // 'invalid_api_key'.
+    pub code: Option<String>,
 }
@@ -0,0 +1,164 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::config;
+use chatgpt_api_cli::context;
+use chatgpt_api_cli::provider;
+
+// The command-line arguments. Each run can select the model, the endpoint, and any of the sampling
+// parameters without recompiling. They override the settings from the config files.
+#[derive(Parser, Debug)]
+#[command(version, about = "Chat with OpenAI's models from the command line.")]
+pub struct Args {
+    /// Prompt to send once, writing only the reply to stdout, instead of chatting. Input piped to
+    /// stdin is appended to it.
+    #[arg(value_name = "PROMPT")]
+    pub prompt_arg: Option<String>,
+
+    /// Same as PROMPT.
+    #[arg(short, long, value_name = "TEXT", conflicts_with = "prompt_arg")]
+    pub prompt: Option<String>,
+
+    /// Profile from the config files to apply.
+    #[arg(short = 'P', long)]
+    pub profile: Option<String>,
+
+    /// Resume the most recently saved session, or the one named with `--resume=NAME`.
+    #[arg(short, long, value_name = "NAME", num_args = 0..=1, require_equals = true)]
+    pub resume: Option<Option<String>>,
+
+    /// Instruction sent to the model as a system message at the start of the chat.
+    #[arg(short, long, value_name = "TEXT", conflicts_with = "persona")]
+    pub system: Option<String>,
+
+    /// Persona whose file in the personas directory holds the system prompt.
+    #[arg(long, value_name = "NAME")]
+    pub persona: Option<String>,
+
+    /// ID of the model to use. Defaults to gpt-3.5-turbo.
+    #[arg(short, long)]
+    pub model: Option<String>,
+
+    /// API to send the chat to: openai (also for compatible servers such as Ollama), azure or
+    /// anthropic. Defaults to openai.
+    #[arg(long, value_name = "PROVIDER")]
+    pub provider: Option<provider::Kind>,
+
+    /// Base URL of the API. Defaults to the provider's, such as https://api.openai.com/v1. For
+    /// Azure, the resource's endpoint.
+    #[arg(long)]
+    pub base_url: Option<String>,
+
+    /// Sampling temperature, between 0 and 2. Defaults to 0.7.
+    #[arg(short, long)]
+    pub temperature: Option<f64>,
+
+    /// Nucleus sampling probability mass, between 0 and 1.
+    #[arg(long)]
+    pub top_p: Option<f64>,
+
+    /// Maximum number of tokens to generate in each reply.
+    #[arg(long)]
+    pub max_tokens: Option<u32>,
+
+    /// Sequence where the model will stop generating. May be repeated up to 4 times.
+    #[arg(long = "stop", value_name = "SEQUENCE")]
+    pub stop: Vec<String>,
+
+    /// Penalty for tokens that have already appeared, between -2 and 2.
+    #[arg(long, allow_negative_numbers = true)]
+    pub presence_penalty: Option<f64>,
+
+    /// Penalty for tokens proportional to their frequency so far, between -2 and 2.
+    #[arg(long, allow_negative_numbers = true)]
+    pub frequency_penalty: Option<f64>,
+
+    /// Bias for a token ID, between -100 and 100, as TOKEN=BIAS. May be repeated.
+    #[arg(long = "logit-bias", value_name = "TOKEN=BIAS", value_parser = parse_logit_bias)]
+    pub logit_bias: Vec<(u32, f64)>,
+
+    /// Identifier of the end-user, sent to help the API detect abuse.
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// Number of alternative replies to generate for each prompt, to choose between. They're
+    /// never streamed.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub n: Option<u32>,
+
+    /// Number of times to retry requests that fail with a rate limit, server error or network
+    /// timeout. Defaults to 4.
+    #[arg(long)]
+    pub max_retries: Option<u32>,
+
+    /// Which messages to send when the chat is too long for the model: full, drop-oldest (the
+    /// default), last-tokens:<N>, or summarize[:<N>] to summarize older turns past N tokens.
+    #[arg(long, value_name = "POLICY")]
+    pub context_policy: Option<context::Policy>,
+
+    /// Let the model read files, list directories, search files and, with approval, run shell
+    /// commands in the tools directory.
+    #[arg(long)]
+    pub tools: bool,
+
+    /// Directory the tools are confined to. Defaults to the current directory.
+    #[arg(long, value_name = "DIR")]
+    pub tools_dir: Option<PathBuf>,
+
+    /// Wait for each complete reply instead of printing it as it's generated.
+    #[arg(long)]
+    pub no_stream: bool,
+
+    /// Print replies as the model wrote them instead of rendering their Markdown.
+    #[arg(long)]
+    pub raw: bool,
+}
+
+impl Args {
+    // The prompt to send in one-shot mode, if one was given.
+    pub fn one_shot_prompt(&self) -> Option<&str> {
+        self.prompt.as_deref().or(self.prompt_arg.as_deref())
+    }
+
+    // The settings given by the arguments, as the top layer of configuration.
+    pub fn settings(&self) -> config::Settings {
+        config::Settings {
+            provider: self.provider,
+            model: self.model.clone(),
+            base_url: self.base_url.clone(),
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            stop: (!self.stop.is_empty()).then(|| self.stop.clone()),
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+            logit_bias: (!self.logit_bias.is_empty()).then(|| {
+                self.logit_bias
+                    .iter()
+                    .map(|(token, bias)| (token.to_string(), *bias))
+                    .collect::<HashMap<_, _>>()
+            }),
+            user: self.user.clone(),
+            n: self.n,
+            system_prompt: self.system.clone(),
+            persona: self.persona.clone(),
+            stream: self.no_stream.then_some(false),
+            raw: self.raw.then_some(true),
+            max_retries: self.max_retries,
+            context_policy: self.context_policy,
+            tools: self.tools.then_some(true),
+            tools_dir: self.tools_dir.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+// Parse a logit bias given as TOKEN=BIAS.
+fn parse_logit_bias(s: &str) -> Result<(u32, f64)> {
+    let (token, bias) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected TOKEN=BIAS, got `{}`", s))?;
+    Ok((token.trim().parse()?, bias.trim().parse()?))
+}
@@ -0,0 +1,532 @@
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use crate::api;
+use crate::image;
+use crate::tools;
+
+// Local files are attached to the next prompt with `/add <path|glob>`, or by mentioning them as
+// `@path` in the prompt itself. Each file is read when it's attached and sent as a user message of
+// its own just before the prompt, with its contents in a fenced block labelled with its path. Until
+// then, `/drop` removes attachments. Once sent, they're part of the chat history like the prompt.
+//
+// A directory or glob attaches the files within it, except hidden files, files ignored by the
+// `.gitignore` files of the Git repository, and binary files. A file named on its own is attached
+// even if it's ignored. Globs support `*`, `?`, `[...]` and `**` for any number of directories.
+//
+// Images are attached with `/image <path>`, or by naming a PNG or JPEG file on its own, and sent as
+// a text part giving the path and an image part. See `image.rs`.
+
+// The most files a directory or glob may attach at once, so that a mistyped pattern doesn't send a
+// whole tree.
+const MAX_FILES: usize = 100;
+
+// Attachment is a file read to be sent with the next prompt.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    // The path the file was attached by, relative to the current directory if it was given so.
+    pub path: String,
+    content: api::Content,
+}
+
+impl Attachment {
+    // Read the text file at the given path. Return None if it's a binary file.
+    fn read(path: &Path) -> Result<Option<Self>> {
+        let display = display_path(path);
+        let bytes = fs::read(path).context(format!("error reading {}", display))?;
+        if tools::is_binary(&bytes) {
+            return Ok(None);
+        }
+        let text = String::from_utf8_lossy(&bytes);
+        Ok(Some(Self {
+            content: fenced(&display, &text).into(),
+            path: display,
+        }))
+    }
+
+    // The message sending the file.
+    pub fn message(&self) -> api::Message {
+        api::Message::new("user", self.content.clone())
+    }
+}
+
+// The path as it's shown to the model and the user, without a leading `./`.
+fn display_path(path: &Path) -> String {
+    path.strip_prefix(".")
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+// The file's path and its contents in a fenced block. The fence is longer than any run of backticks
+// in the contents so that it can't be closed early.
+fn fenced(path: &str, contents: &str) -> String {
+    let longest_run = contents
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let language = Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let contents = contents.strip_suffix('\n').unwrap_or(contents);
+    format!(
+        "File `{}`:\n{}{}\n{}\n{}",
+        path, fence, language, contents, fence
+    )
+}
+
+// Read the PNG or JPEG image at the given path, to be sent as an image for vision-capable models.
+pub fn image(path: &Path) -> Result<Attachment> {
+    let display = display_path(path);
+    let url = image::data_url(path)?;
+    Ok(Attachment {
+        content: api::Content::Parts(vec![
+            api::ContentPart::Text {
+                text: format!("Image `{}`:", display),
+            },
+            api::ContentPart::ImageUrl {
+                image_url: api::ImageUrl { url, detail: None },
+            },
+        ]),
+        path: display,
+    })
+}
+
+// Read the files given by a path to a file or directory, or by a glob.
+pub fn read(pattern: &str) -> Result<Vec<Attachment>> {
+    let path = Path::new(pattern);
+    if path.is_file() {
+        if is_image(path) {
+            return Ok(vec![image(path)?]);
+        }
+        return match Attachment::read(path)? {
+            Some(attachment) => Ok(vec![attachment]),
+            None => bail!("{} is a binary file", pattern),
+        };
+    }
+    let (base, glob) = if path.is_dir() {
+        (path.to_path_buf(), None)
+    } else if is_glob(pattern) {
+        let (base, rest) = split_glob(pattern);
+        (base, Some(glob_regex(rest)?))
+    } else {
+        bail!("can't find {}", pattern);
+    };
+
+    let mut ignore = Ignore::new(&base);
+    let entries = walkdir::WalkDir::new(&base)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0
+                || !e.file_name().to_string_lossy().starts_with('.')
+                    && !ignore.is_ignored(e.path(), e.file_type().is_dir())
+        });
+    let mut attachments = vec![];
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        // The glob's wildcards are matched against the path below its base directory.
+        if let Some(glob) = &glob {
+            let relative = entry.path().strip_prefix(&base).unwrap_or(entry.path());
+            if !glob.is_match(&relative.to_string_lossy()) {
+                continue;
+            }
+        }
+        if let Some(attachment) = Attachment::read(entry.path())? {
+            attachments.push(attachment);
+        }
+        if attachments.len() > MAX_FILES {
+            bail!(
+                "{} matches more than {} files; give a narrower path or glob",
+                pattern,
+                MAX_FILES
+            );
+        }
+    }
+    if attachments.is_empty() {
+        bail!("no text files match {}", pattern);
+    }
+    Ok(attachments)
+}
+
+// The paths referenced in the text as `@path`: words starting with `@` that name an existing file
+// or directory. Punctuation ending a sentence after the path is ignored.
+pub fn references(text: &str) -> Vec<String> {
+    let mut paths: Vec<String> = vec![];
+    for word in text.split_whitespace() {
+        let Some(path) = word.strip_prefix('@') else {
+            continue;
+        };
+        let path = path.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'', '"']);
+        if !path.is_empty() && Path::new(path).exists() && !paths.iter().any(|p| p == path) {
+            paths.push(path.to_string());
+        }
+    }
+    paths
+}
+
+// Whether the attached path is given by the pattern: the same path, a file within the directory, or
+// a path matching the glob.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+    let dir = pattern.trim_end_matches('/');
+    path == pattern
+        || path.starts_with(&format!("{}/", dir))
+        || is_glob(pattern) && glob_regex(pattern).is_ok_and(|glob| glob.is_match(path))
+}
+
+// Whether the file is a PNG or JPEG image.
+fn is_image(path: &Path) -> bool {
+    let mut start = [0; 8];
+    fs::File::open(path)
+        .and_then(|mut file| file.read(&mut start))
+        .is_ok_and(|n| image::media_type(&start[..n]).is_some())
+}
+
+// Whether the text is a glob rather than a plain path.
+fn is_glob(text: &str) -> bool {
+    text.contains(['*', '?', '['])
+}
+
+// Split the glob into the directory before its first wildcard, where the search starts, and the
+// rest.
+fn split_glob(pattern: &str) -> (PathBuf, &str) {
+    let wildcard = pattern.find(['*', '?', '[']).unwrap_or(pattern.len());
+    match pattern[..wildcard].rfind('/') {
+        Some(0) => (PathBuf::from("/"), &pattern[1..]),
+        Some(slash) => (PathBuf::from(&pattern[..slash]), &pattern[slash + 1..]),
+        None => (PathBuf::from("."), pattern),
+    }
+}
+
+// The regular expression matching the paths that the glob matches.
+fn glob_regex(pattern: &str) -> Result<Regex> {
+    Regex::new(&format!("^{}$", glob_to_regex(pattern)))
+        .context(format!("invalid glob: {}", pattern))
+}
+
+// Translate a glob into a regular expression, without anchors. As in Git, `**` matches any number
+// of directories only as a whole component; elsewhere it's the same as `*`. A backslash makes the
+// next character literal, and a `[` without a closing `]` is literal too.
+fn glob_to_regex(pattern: &str) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let stars = chars[i..].iter().take_while(|&&c| c == '*').count();
+                let starts_component = i == 0 || chars[i - 1] == '/';
+                let next = chars.get(i + stars);
+                i += stars;
+                if starts_component && next == Some(&'/') {
+                    i += 1;
+                    regex.push_str("(?:.*/)?");
+                } else if starts_component && next.is_none() {
+                    regex.push_str(".*");
+                } else {
+                    regex.push_str("[^/]*");
+                }
+                continue;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match class_to_regex(&chars[i + 1..]) {
+                Some((class, len)) => {
+                    regex.push_str(&class);
+                    i += len;
+                }
+                None => regex.push_str(r"\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                regex.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex
+}
+
+// Translate the bracket expression starting after a `[` into a regular expression's character
+// class. Return it with the number of characters it took up, including the closing `]`, or None if
+// there's no closing `]`. A `]` first in the class, after any `!` or `^`, is a member of it.
+fn class_to_regex(chars: &[char]) -> Option<(String, usize)> {
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    let first = usize::from(negated);
+    let mut members = String::new();
+    let mut i = first;
+    loop {
+        match *chars.get(i)? {
+            ']' if i > first => break,
+            '-' if i > first && chars.get(i + 1).is_some_and(|&c| c != ']') => members.push('-'),
+            '\\' => {
+                i += 1;
+                members.push_str(&regex::escape(&chars.get(i)?.to_string()));
+            }
+            c => members.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    // Like `*` and `?`, a class never matches the separator.
+    let class = match negated {
+        true => format!("[^{}/]", members),
+        false => format!("[{}&&[^/]]", members),
+    };
+    Some((class, i + 1))
+}
+
+// Rule is a pattern from a `.gitignore` file.
+struct Rule {
+    regex: Regex,
+    // Whether the pattern re-includes what an earlier one excluded.
+    negated: bool,
+    // Whether the pattern only matches directories.
+    dir_only: bool,
+    // Whether the pattern is matched against the path relative to the `.gitignore` file's
+    // directory, rather than against the name alone.
+    anchored: bool,
+}
+
+// Ignore reads the `.gitignore` files that apply to a search and tells which paths they ignore.
+// It supports the common forms of patterns, but not global or per-repository exclude files.
+pub(crate) struct Ignore {
+    // The top directory of the Git repository, if the search is within one.
+    root: Option<PathBuf>,
+    // The rules of the `.gitignore` file in each directory read so far.
+    rules: HashMap<PathBuf, Vec<Rule>>,
+}
+
+impl Ignore {
+    pub(crate) fn new(base: &Path) -> Self {
+        let root = base.canonicalize().ok().and_then(|base| {
+            base.ancestors()
+                .find(|d| d.join(".git").exists())
+                .map(Path::to_path_buf)
+        });
+        Self {
+            root,
+            rules: HashMap::new(),
+        }
+    }
+
+    // Whether the path is ignored by the `.gitignore` files of the repository. As in Git, a path
+    // within an ignored directory is ignored too, whatever the rules say about the path itself.
+    pub(crate) fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let Some(root) = self.root.clone() else {
+            return false;
+        };
+        let Some(path) = path
+            .parent()
+            .and_then(|dir| dir.canonicalize().ok())
+            .zip(path.file_name())
+            .map(|(dir, name)| dir.join(name))
+        else {
+            return false;
+        };
+        let Ok(relative) = path.strip_prefix(&root) else {
+            return false;
+        };
+        let components: Vec<_> = relative.components().collect();
+        (1..=components.len())
+            .any(|n| self.excludes(&root, &components[..n], n < components.len() || is_dir))
+    }
+
+    // Whether the rules of the `.gitignore` files of the directories above the path, given by its
+    // components below the root, exclude it. Later rules override earlier ones, and those of
+    // deeper directories override those above them.
+    fn excludes(&mut self, root: &Path, components: &[Component], is_dir: bool) -> bool {
+        let mut ignored = false;
+        let mut dir = root.to_path_buf();
+        let name = components
+            .last()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .unwrap_or_default();
+        for i in 0..components.len() {
+            // The path relative to the directory whose rules are being applied.
+            let rest: PathBuf = components[i..].iter().collect();
+            let rest = rest.to_string_lossy();
+            for rule in self.rules_for(&dir) {
+                if rule.dir_only && !is_dir {
+                    continue;
+                }
+                let subject = if rule.anchored { &rest } else { &name };
+                if rule.regex.is_match(subject) {
+                    ignored = !rule.negated;
+                }
+            }
+            dir.push(components[i]);
+        }
+        ignored
+    }
+
+    // The rules of the `.gitignore` file in the directory, read the first time they're needed.
+    fn rules_for(&mut self, dir: &Path) -> &[Rule] {
+        self.rules.entry(dir.to_path_buf()).or_insert_with(|| {
+            match fs::read_to_string(dir.join(".gitignore")) {
+                Ok(text) => text.lines().filter_map(parse_rule).collect(),
+                Err(_) => vec![],
+            }
+        })
+    }
+}
+
+// Parse a line of a `.gitignore` file, unless it's blank or a comment.
+fn parse_rule(line: &str) -> Option<Rule> {
+    // Trailing spaces are ignored, unless the last is escaped with a backslash.
+    let trimmed = line.trim_end_matches(' ');
+    let line = match trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        true => &line[..trimmed.len() + 1],
+        false => trimmed,
+    };
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    // A pattern with a slash other than at its end is relative to the `.gitignore` file's
+    // directory.
+    let anchored = line.contains('/');
+    let line = line.strip_prefix('/').unwrap_or(line);
+    let regex = Regex::new(&format!("^{}$", glob_to_regex(line))).ok()?;
+    Some(Rule {
+        regex,
+        negated,
+        dir_only,
+        anchored,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob_matches(pattern: &str, path: &str) -> bool {
+        glob_regex(pattern).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn wildcards_stay_within_a_component() {
+        assert!(glob_matches("*.rs", "main.rs"));
+        assert!(!glob_matches("*.rs", "src/main.rs"));
+        assert!(glob_matches("src/?.rs", "src/a.rs"));
+        assert!(!glob_matches("src?a.rs", "src/a.rs"));
+        assert!(!glob_matches("a**b", "a/b"));
+        assert!(glob_matches("a**b", "axxb"));
+    }
+
+    #[test]
+    fn double_star_matches_directories() {
+        assert!(glob_matches("**/*.rs", "main.rs"));
+        assert!(glob_matches("**/*.rs", "src/bin/main.rs"));
+        assert!(glob_matches("src/**", "src/bin/main.rs"));
+        assert!(glob_matches("src/**/main.rs", "src/main.rs"));
+        assert!(glob_matches("src/**/main.rs", "src/a/b/main.rs"));
+        assert!(!glob_matches("src/**/main.rs", "srcmain.rs"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob_matches("[abc].txt", "b.txt"));
+        assert!(!glob_matches("[abc].txt", "d.txt"));
+        assert!(glob_matches("[a-c].txt", "b.txt"));
+        assert!(glob_matches("[!abc].txt", "d.txt"));
+        assert!(!glob_matches("[!abc].txt", "a.txt"));
+        assert!(glob_matches("[]a].txt", "].txt"));
+        assert!(glob_matches("[a-].txt", "-.txt"));
+        assert!(glob_matches("[&~].txt", "~.txt"));
+        assert!(!glob_matches("a[/]b", "a/b"));
+        assert!(!glob_matches("a[!x]b", "a/b"));
+    }
+
+    #[test]
+    fn unclosed_class_is_literal() {
+        assert!(glob_matches("[abc", "[abc"));
+        assert!(!glob_matches("[abc", "a"));
+        assert!(glob_matches("[!]", "[!]"));
+    }
+
+    #[test]
+    fn backslash_escapes() {
+        assert!(glob_matches(r"\*.txt", "*.txt"));
+        assert!(!glob_matches(r"\*.txt", "a.txt"));
+        assert!(glob_matches(r"[\]].txt", "].txt"));
+    }
+
+    #[test]
+    fn rules() {
+        assert!(parse_rule("").is_none());
+        assert!(parse_rule("# comment").is_none());
+
+        let rule = parse_rule("!build/").unwrap();
+        assert!(rule.negated && rule.dir_only && !rule.anchored);
+        assert!(rule.regex.is_match("build"));
+
+        assert!(parse_rule("/build").unwrap().anchored);
+        assert!(parse_rule("doc/build").unwrap().anchored);
+        assert!(parse_rule(r"\#notes").unwrap().regex.is_match("#notes"));
+        assert!(parse_rule(r"\!notes").unwrap().regex.is_match("!notes"));
+        assert!(parse_rule("notes  ").unwrap().regex.is_match("notes"));
+        assert!(parse_rule(r"notes\ ").unwrap().regex.is_match("notes "));
+    }
+
+    #[test]
+    fn ignored_paths() {
+        let repo = tempfile::tempdir().unwrap();
+        let root = repo.path();
+        for dir in [".git", "src", "target", "sub", "docs/api"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(
+            root.join(".gitignore"),
+            "target/\n!target/keep.rs\n*.log\n!keep.log\n/top.txt\ndocs/*.md\nout/\n",
+        )
+        .unwrap();
+        fs::write(root.join("sub/.gitignore"), "!b.log\n").unwrap();
+        fs::write(root.join("out"), "").unwrap();
+
+        let mut ignore = Ignore::new(root);
+        let mut ignored = |path: &str, is_dir| ignore.is_ignored(&root.join(path), is_dir);
+        assert!(ignored("target", true));
+        assert!(ignored("target/main.rs", false));
+        // A file can't be re-included from within an ignored directory.
+        assert!(ignored("target/keep.rs", false));
+        // Directory rules don't match files.
+        assert!(!ignored("out", false));
+        assert!(ignored("a.log", false));
+        assert!(!ignored("keep.log", false));
+        assert!(ignored("sub/a.log", false));
+        // Deeper rules override those above them.
+        assert!(!ignored("sub/b.log", false));
+        // Anchored rules match relative to their directory.
+        assert!(ignored("top.txt", false));
+        assert!(!ignored("sub/top.txt", false));
+        assert!(ignored("docs/a.md", false));
+        assert!(!ignored("docs/api/a.md", false));
+        assert!(!ignored("src/main.rs", false));
+    }
+
+    #[test]
+    fn nothing_is_ignored_outside_a_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(".gitignore"), "*\n").unwrap();
+        assert!(!Ignore::new(dir.path()).is_ignored(&dir.path().join("a.txt"), false));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::api;
+use crate::client::{Client, Result};
+use crate::openai;
+use crate::provider::{BoxFuture, Kind, Provider};
+
+// Azure OpenAI serves OpenAI's chat completions API from a URL for each deployment of a model in
+// an Azure resource, authenticated by an `api-key` header. The base URL is the resource's
+// endpoint, such as `https://<resource>.openai.azure.com`, and the API version is given with each
+// request.
+// https://learn.microsoft.com/azure/ai-services/openai/reference
+
+// The API version requested, unless configured otherwise.
+pub const DEFAULT_API_VERSION: &str = "2024-10-21";
+
+// Azure sends requests to a deployment in an Azure OpenAI resource.
+pub struct Azure {
+    client: Client,
+    api_key: String,
+    // The name of the deployment. If None, the model's name is used, as deployments are often named
+    // after their models.
+    deployment: Option<String>,
+    api_version: String,
+}
+
+impl Azure {
+    pub fn new(
+        client: Client,
+        api_key: String,
+        deployment: Option<String>,
+        api_version: Option<String>,
+    ) -> Self {
+        Self {
+            client,
+            api_key,
+            deployment,
+            api_version: api_version.unwrap_or_else(|| DEFAULT_API_VERSION.to_string()),
+        }
+    }
+
+    // The URL of the deployment's chat completions endpoint.
+    fn url(&self) -> String {
+        let deployment = self
+            .deployment
+            .as_deref()
+            .unwrap_or(&self.client.options().model);
+        self.client.url(&format!(
+            "/openai/deployments/{}/chat/completions?api-version={}",
+            deployment, self.api_version
+        ))
+    }
+
+    fn headers(&self) -> Vec<(&'static str, String)> {
+        vec![("api-key", self.api_key.clone())]
+    }
+}
+
+impl Provider for Azure {
+    fn kind(&self) -> Kind {
+        Kind::Azure
+    }
+
+    fn client(&self) -> &Client {
+        &self.client
+    }
+
+    fn client_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    fn send_choices<'a>(
+        &'a self,
+        messages: &'a [api::Message],
+        n: u32,
+        tools: &'a [api::Tool],
+    ) -> BoxFuture<'a, Result<(Vec<api::Message>, api::Usage)>> {
+        Box::pin(openai::send_choices(
+            &self.client,
+            self.url(),
+            self.headers(),
+            messages,
+            n,
+            tools,
+        ))
+    }
+
+    fn send_streaming<'a>(
+        &'a self,
+        messages: &'a [api::Message],
+        tools: &'a [api::Tool],
+        on_delta: &'a mut (dyn FnMut(&str) + Send),
+    ) -> BoxFuture<'a, Result<(api::Message, Option<api::Usage>)>> {
+        Box::pin(openai::send_streaming(
+            &self.client,
+            self.url(),
+            self.headers(),
+            messages,
+            tools,
+            on_delta,
+        ))
+    }
+}
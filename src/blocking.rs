@@ -0,0 +1,103 @@
+use anyhow::Result;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+
+use crate::bot;
+
+// The REPL and one-shot mode are synchronous: they read the user's input and print the replies on
+// the main thread. `ChatBot` here wraps the async `bot::ChatBot` with a single-threaded tokio
+// runtime and runs each request to completion before returning. Everything that doesn't send a
+// request is reached through the async chatbot, which it dereferences to.
+//
+// With `set_cancel_on_ctrl_c`, pressing Ctrl-C while a request is running cancels it and returns
+// `Cancelled`, instead of killing the program. The chat history is left as it would be after an
+// error, so the prompt can be resent with `/retry`. A tool call that's running is told to stop,
+// and a shell command is killed with the processes it started. Once a request has been run this
+// way, Ctrl-C no longer ends the process by default, so programs handling it themselves should
+// leave this off.
+
+// Cancelled is the error returned for a request cancelled with Ctrl-C.
+#[derive(Debug, thiserror::Error)]
+#[error("cancelled")]
+pub struct Cancelled;
+
+// ChatBot sends requests synchronously. See `bot::ChatBot`.
+pub struct ChatBot {
+    bot: bot::ChatBot,
+    runtime: tokio::runtime::Runtime,
+    // Whether Ctrl-C cancels a running request.
+    cancel_on_ctrl_c: bool,
+}
+
+impl ChatBot {
+    pub fn new(bot: bot::ChatBot) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            bot,
+            runtime,
+            cancel_on_ctrl_c: false,
+        })
+    }
+
+    // Set whether pressing Ctrl-C cancels a running request.
+    pub fn set_cancel_on_ctrl_c(&mut self, cancel: bool) {
+        self.cancel_on_ctrl_c = cancel;
+    }
+
+    // See `bot::ChatBot::chat`.
+    pub fn chat<F: FnMut(&str) + Send>(&mut self, text: &str, on_delta: F) -> Result<bot::Reply> {
+        run(
+            &self.runtime,
+            self.cancel_on_ctrl_c,
+            self.bot.chat(text, on_delta),
+        )
+    }
+
+    // See `bot::ChatBot::send`.
+    pub fn send<F: FnMut(&str) + Send>(&mut self, on_delta: F) -> Result<bot::Reply> {
+        run(
+            &self.runtime,
+            self.cancel_on_ctrl_c,
+            self.bot.send(on_delta),
+        )
+    }
+
+    // See `bot::ChatBot::summarize`.
+    pub fn summarize(&mut self) -> Result<usize> {
+        run(&self.runtime, self.cancel_on_ctrl_c, self.bot.summarize())
+    }
+}
+
+impl Deref for ChatBot {
+    type Target = bot::ChatBot;
+
+    fn deref(&self) -> &bot::ChatBot {
+        &self.bot
+    }
+}
+
+impl DerefMut for ChatBot {
+    fn deref_mut(&mut self) -> &mut bot::ChatBot {
+        &mut self.bot
+    }
+}
+
+// Run the future on the runtime until it completes. If `cancel_on_ctrl_c`, stop when Ctrl-C is
+// pressed instead, dropping the future, which cancels its request.
+fn run<T>(
+    runtime: &tokio::runtime::Runtime,
+    cancel_on_ctrl_c: bool,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    runtime.block_on(async {
+        if !cancel_on_ctrl_c {
+            return future.await;
+        }
+        tokio::select! {
+            result = future => result,
+            _ = tokio::signal::ctrl_c() => Err(Cancelled.into()),
+        }
+    })
+}
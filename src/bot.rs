@@ -1,24 +1,70 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
+use log::warn;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
 
 use crate::api;
+use crate::attach;
+use crate::budget;
 use crate::client;
+use crate::context;
+use crate::cost;
+use crate::provider;
+use crate::session;
+use crate::tokens;
+use crate::tools;
+
+// The summary of older turns is kept as a system message starting with this prefix.
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation:\n";
+
+// The instruction sent with the turns to be summarized.
+const SUMMARIZE_PROMPT: &str = "Summarize the following conversation between a user and an \
+assistant so that it can be continued without the original. Keep the decisions made, the facts, \
+names, code and open questions needed to follow the rest of the conversation. If it begins with \
+an earlier summary, fold that into the new summary. Reply with only the summary.";
+
+// The most rounds of tool calls made in answer to one prompt, so that a model calling tools in a
+// loop can't run up costs.
+const MAX_TOOL_ROUNDS: usize = 10;
+
+// The number of most recent messages that are never summarized, so that the model sees the latest
+// exchanges verbatim.
+const KEEP_RECENT_MESSAGES: usize = 4;
+
+// Whether the message is the summary of older turns.
+fn is_summary(message: &api::Message) -> bool {
+    message.role == "system"
+        && message
+            .text()
+            .is_some_and(|c| c.starts_with(SUMMARY_PREFIX))
+}
 
 // Chat holds and manages the history of structured chat messages.
 struct Chat {
     messages: Vec<api::Message>,
+    // The indices of the messages that are always sent, however long the chat gets.
+    pinned: BTreeSet<usize>,
+    // The instruction that starts each chat, if any.
+    system_prompt: Option<String>,
+    // The alternative replies that weren't kept in the history.
+    discarded: Vec<session::Discarded>,
 }
 
 impl Chat {
-    fn new() -> Self {
-        Self { messages: vec![] }
+    fn new(system_prompt: Option<String>) -> Self {
+        let mut chat = Self {
+            messages: vec![],
+            pinned: BTreeSet::new(),
+            system_prompt,
+            discarded: vec![],
+        };
+        chat.clear();
+        chat
     }
 
     // Add a text line from the user by structuring it as a chat message and storing it.
     fn add_user_text(&mut self, text: &str) {
-        self.messages.push(api::Message {
-            role: "user".to_string(),
-            content: Some(text.to_string()),
-        });
+        self.messages.push(api::Message::new("user", text));
     }
 
     // Add an already-structured message to the chat history.
@@ -26,51 +72,803 @@ impl Chat {
         self.messages.push(message);
     }
 
-    // Remove the context given to the chatbot with each request by clearing the chat history.
+    // Replace the system prompt. It's kept at the head of the chat history, where it's always sent.
+    fn set_system_prompt(&mut self, prompt: &str) {
+        let message = api::Message::new("system", prompt);
+        match self.messages.first() {
+            Some(first) if first.role == "system" && !is_summary(first) => {
+                self.messages[0] = message
+            }
+            _ => {
+                self.messages.insert(0, message);
+                self.pinned = self.pinned.iter().map(|i| i + 1).collect();
+            }
+        }
+        self.system_prompt = Some(prompt.to_string());
+    }
+
+    // Remove the context given to the chatbot with each request by clearing the chat history. The
+    // system prompt is kept.
     fn clear(&mut self) {
         self.messages.clear();
+        self.pinned.clear();
+        self.discarded.clear();
+        if let Some(prompt) = &self.system_prompt {
+            self.messages
+                .push(api::Message::new("system", prompt.as_str()));
+        }
     }
+
+    // Pin the latest turn: the user's last prompt, the files attached to it and the replies to it.
+    // Return the number of messages pinned.
+    fn pin_last_turn(&mut self) -> usize {
+        let start = match (0..self.messages.len())
+            .rev()
+            .find(|&i| context::starts_turn(&self.messages, i))
+        {
+            Some(start) => start,
+            None => return 0,
+        };
+        self.pinned.extend(start..self.messages.len());
+        self.messages.len() - start
+    }
+
+    // The indices of the oldest messages that can be summarized: the turns before the most recent
+    // messages except the system prompt and pinned messages. An earlier summary is included so that
+    // it's folded into the new one.
+    fn summarizable(&self) -> Vec<usize> {
+        // End at the start of a turn so that no reply is separated from its question.
+        let mut end = self.messages.len().saturating_sub(KEEP_RECENT_MESSAGES);
+        while end > 0 && !context::starts_turn(&self.messages, end) {
+            end -= 1;
+        }
+        (0..end)
+            .filter(|i| !self.pinned.contains(i))
+            .filter(|&i| self.messages[i].role != "system" || is_summary(&self.messages[i]))
+            .collect()
+    }
+
+    // Replace the messages at the given indices, in increasing order, with the summary, which takes
+    // the place of the first of them.
+    fn replace_with_summary(&mut self, indices: &[usize], summary: api::Message) {
+        let first = indices[0];
+        let removed: BTreeSet<usize> = indices.iter().copied().collect();
+        let mut messages = Vec::with_capacity(self.messages.len() - indices.len() + 1);
+        let mut pinned = BTreeSet::new();
+        for (i, message) in self.messages.drain(..).enumerate() {
+            if i == first {
+                messages.push(summary.clone());
+            }
+            if removed.contains(&i) {
+                continue;
+            }
+            if self.pinned.contains(&i) {
+                pinned.insert(messages.len());
+            }
+            messages.push(message);
+        }
+        self.messages = messages;
+        self.pinned = pinned;
+    }
+
+    // The index of the summary of older turns, if there is one.
+    fn summary_index(&self) -> Option<usize> {
+        self.messages.iter().position(is_summary)
+    }
+}
+
+// Reply describes the model's response to the chat history. The text itself is delivered through
+// the `on_delta` callback of `ChatBot::send`.
+pub struct Reply {
+    // The tokens used for the request and response, if the API reported them.
+    pub usage: Option<api::Usage>,
+    // The cost in US dollars of the request and response, if the tokens and the model's price are
+    // known.
+    pub cost: Option<f64>,
+    // The number of older messages left out of the request to fit the context window.
+    pub dropped: usize,
+    // The number of older messages replaced by a summary before the request was sent.
+    pub summarized: usize,
 }
 
-// ChatBot holds the chat history and the client that sends the chat history to the API.
+// Choose is asked which of several alternative replies to keep in the chat history. It's given
+// their texts and returns the index of the one chosen. It's responsible for showing them.
+pub type Choose = Box<dyn Fn(&[String]) -> usize + Send + Sync>;
+
+// ChatBot holds the chat history and the provider that sends the chat history to an API. Sending is
+// async, and dropping the future of a request cancels it. The functions it asks for confirmations
+// and choices, and the tools it calls, run synchronously while it waits. See `blocking.rs` for
+// running it from synchronous code.
 pub struct ChatBot {
     chat: Chat,
-    client: client::Client,
+    provider: Box<dyn provider::Provider>,
+    // Whether to ask the API to stream replies as they're generated.
+    stream: bool,
+    // Which messages to send when the history doesn't fit the context window.
+    context_policy: context::Policy,
+    // The model's context window in tokens, if it's known better than `context::context_window`.
+    context_window: Option<usize>,
+    pricing: cost::Pricing,
+    // The requests made by this chatbot, for reporting the session's cost.
+    costs: cost::Totals,
+    // The file each request's cost is recorded in, if any.
+    ledger: Option<PathBuf>,
+    limits: budget::Limits,
+    // Asked whether to send requests over a soft limit. Without it, they're refused.
+    confirm: Option<budget::Confirm>,
+    // Asked which of several alternative replies to keep. Without it, the first is kept.
+    choose: Option<Choose>,
+    // The tools the model may call.
+    tools: tools::Registry,
+    // The files to send with the next prompt.
+    attachments: Vec<attach::Attachment>,
 }
 
 impl ChatBot {
-    pub fn new(auth_token: String) -> Self {
+    pub fn new(
+        provider: Box<dyn provider::Provider>,
+        stream: bool,
+        system_prompt: Option<String>,
+    ) -> Self {
         Self {
-            chat: Chat::new(),
-            client: client::Client::new(auth_token),
+            chat: Chat::new(system_prompt),
+            provider,
+            stream,
+            context_policy: context::Policy::default(),
+            context_window: None,
+            pricing: cost::Pricing::default(),
+            costs: cost::Totals::default(),
+            ledger: None,
+            limits: budget::Limits::default(),
+            confirm: None,
+            choose: None,
+            tools: tools::Registry::new(vec![]),
+            attachments: vec![],
+        }
+    }
+
+    // Set the tools the model may call.
+    pub fn set_tools(&mut self, tools: tools::Registry) {
+        self.tools = tools;
+    }
+
+    // Set the function asked whether a tool call with side effects may run.
+    pub fn set_approve(&mut self, approve: tools::Approve) {
+        self.tools.set_approve(approve);
+    }
+
+    // Set the function asked which of several alternative replies to keep.
+    pub fn set_choose(&mut self, choose: Choose) {
+        self.choose = Some(choose);
+    }
+
+    // Set the limits on the size of requests and on spending.
+    pub fn set_limits(&mut self, limits: budget::Limits) {
+        self.limits = limits;
+    }
+
+    // Set the function asked whether to send a request that's over a soft limit.
+    pub fn set_confirm(&mut self, confirm: budget::Confirm) {
+        self.confirm = Some(confirm);
+    }
+
+    // Check that sending the messages keeps within the limits, asking for confirmation of a request
+    // over a soft limit. Return the overrun as the error if the request mustn't be sent.
+    fn check_budget(&self, messages: &[api::Message]) -> Result<()> {
+        let prompt_tokens = tokens::Counter::for_model(self.model()).messages(messages) as u64;
+        let options = self.provider.client().options();
+        let completion_tokens =
+            options.max_tokens.unwrap_or(0) as u64 * options.n.unwrap_or(1) as u64;
+        let estimate = budget::Estimate {
+            tokens: prompt_tokens + completion_tokens,
+            cost: self
+                .pricing
+                .price(self.model())
+                .map(|price| price.cost(prompt_tokens, completion_tokens)),
+        };
+        let Some(overrun) =
+            self.limits
+                .check(&estimate, self.costs.cost, self.ledger.as_deref())?
+        else {
+            return Ok(());
+        };
+        let confirmed = overrun.severity == budget::Severity::Soft
+            && self
+                .confirm
+                .as_ref()
+                .is_some_and(|confirm| confirm(&overrun));
+        if confirmed {
+            Ok(())
+        } else {
+            Err(overrun.into())
+        }
+    }
+
+    // Set the prices used to work out the cost of requests.
+    pub fn set_pricing(&mut self, pricing: cost::Pricing) {
+        self.pricing = pricing;
+    }
+
+    // Set the file each request's cost is recorded in. Without one, costs are only kept for the
+    // session.
+    pub fn set_ledger(&mut self, ledger: Option<PathBuf>) {
+        self.ledger = ledger;
+    }
+
+    // The file each request's cost is recorded in, if any.
+    pub fn ledger(&self) -> Option<&Path> {
+        self.ledger.as_deref()
+    }
+
+    // The totals of the requests made in this session.
+    pub fn costs(&self) -> cost::Totals {
+        self.costs
+    }
+
+    // Add a request's usage to the session's totals and record it in the ledger, if any. Return its
+    // cost, if the model's price is known.
+    fn record_usage(&mut self, usage: &api::Usage) -> Option<f64> {
+        let entry = cost::Entry::new(self.model(), usage, &self.pricing);
+        self.costs.add(&entry);
+        // The reply is worth more than the record of its cost.
+        if let Some(ledger) = &self.ledger {
+            if let Err(e) = cost::record(ledger, &entry) {
+                warn!("error recording the cost of a request: {:#}", e);
+            }
         }
+        entry.cost
     }
 
-    // Add the user's text to the chat history and send it to the API. On success, return the
-    // model's response text and the number of tokens used.
-    pub fn chat(&mut self, text: &str) -> Result<(String, u32)> {
+    // Set how the history is trimmed to fit the context window, and override the size of the window
+    // if given.
+    pub fn set_context(&mut self, policy: context::Policy, window: Option<usize>) {
+        self.context_policy = policy;
+        self.context_window = window;
+    }
+
+    // The name of the model the chat is sent to.
+    pub fn model(&self) -> &str {
+        &self.provider.client().options().model
+    }
+
+    // The model and parameters the chat is sent with.
+    pub fn options(&self) -> &client::Options {
+        self.provider.client().options()
+    }
+
+    // Replace the model and parameters the chat is sent with.
+    pub fn set_options(&mut self, options: client::Options) {
+        self.provider.client_mut().set_options(options);
+    }
+
+    // Estimate the number of prompt tokens that sending the chat history will use, after adding the
+    // user's text and the attached files if given.
+    pub fn prompt_tokens(&self, text: Option<&str>) -> usize {
+        let mut messages = self.chat.messages.clone();
+        if let Some(text) = text {
+            messages.extend(self.attachments.iter().map(|a| a.message()));
+            messages.push(api::Message::new("user", text));
+        }
+        self.fit(&messages).tokens
+    }
+
+    // The number of prompt tokens that fit in the context window, leaving room for the reply.
+    fn window_budget(&self) -> usize {
+        let options = self.provider.client().options();
+        let window = self
+            .context_window
+            .unwrap_or_else(|| context::context_window(&options.model));
+        let reply_tokens = options
+            .max_tokens
+            .map_or(context::DEFAULT_REPLY_TOKENS, |t| t as usize);
+        window.saturating_sub(reply_tokens)
+    }
+
+    // Choose the messages to send so that they fit in the context window, leaving room for the
+    // reply.
+    fn fit(&self, messages: &[api::Message]) -> context::Fitted {
+        context::fit(
+            messages,
+            &self.chat.pinned,
+            self.context_policy,
+            self.window_budget(),
+            &tokens::Counter::for_model(self.model()),
+        )
+    }
+
+    // Summarize the oldest turns if the context policy calls for it because the history has grown
+    // past its threshold. Return the number of messages summarized.
+    async fn summarize_if_needed(&mut self) -> Result<usize> {
+        let threshold = match self.context_policy {
+            context::Policy::Summarize(threshold) => threshold.unwrap_or(self.window_budget()),
+            _ => return Ok(0),
+        };
+        let counter = tokens::Counter::for_model(self.model());
+        if counter.messages(&self.chat.messages) <= threshold {
+            return Ok(0);
+        }
+        self.summarize().await
+    }
+
+    // Replace the oldest turns in the chat history with a system message summarizing them, written
+    // by the model. The most recent messages, the system prompt and pinned messages are kept. An
+    // earlier summary is folded into the new one. Return the number of messages summarized.
+    pub async fn summarize(&mut self) -> Result<usize> {
+        let indices = self.chat.summarizable();
+        // Don't count an earlier summary as a summarized message.
+        let count = indices
+            .iter()
+            .filter(|&&i| !is_summary(&self.chat.messages[i]))
+            .count();
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let transcript = indices
+            .iter()
+            .map(|&i| {
+                let m = &self.chat.messages[i];
+                let mut text = format!("{}: {}", m.role, m.text().unwrap_or_default());
+                for call in m.tool_calls.iter().flatten() {
+                    text.push_str(&format!(
+                        "\n(called {} with {})",
+                        call.function.name, call.function.arguments
+                    ));
+                }
+                text
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let request = [
+            api::Message::new("system", SUMMARIZE_PROMPT),
+            api::Message::new("user", transcript),
+        ];
+        self.check_budget(&request)?;
+        let (reply, usage) = self.provider.send(&request).await?;
+        self.record_usage(&usage);
+        let summary = reply.text().ok_or(anyhow!("no summary received"))?;
+
+        self.chat.replace_with_summary(
+            &indices,
+            api::Message::new("system", format!("{}{}", SUMMARY_PREFIX, summary.trim())),
+        );
+        Ok(count)
+    }
+
+    // The text of the summary of older turns, if they've been summarized.
+    pub fn summary(&self) -> Option<&str> {
+        let i = self.chat.summary_index()?;
+        self.chat.messages[i]
+            .content
+            .as_ref()
+            .and_then(api::Content::as_text)
+            .and_then(|c| c.strip_prefix(SUMMARY_PREFIX))
+    }
+
+    // Replace the text of the summary of older turns. Fails if they haven't been summarized.
+    pub fn set_summary(&mut self, text: &str) -> Result<()> {
+        let i = self
+            .chat
+            .summary_index()
+            .ok_or(anyhow!("there's no summary yet"))?;
+        self.chat.messages[i].content = Some(format!("{}{}", SUMMARY_PREFIX, text.trim()).into());
+        Ok(())
+    }
+
+    // The system prompt, if any.
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.chat.system_prompt.as_deref()
+    }
+
+    // Replace the system prompt with the given instruction.
+    pub fn set_system_prompt(&mut self, prompt: &str) {
+        self.chat.set_system_prompt(prompt);
+    }
+
+    // Pin the latest turn so that it's always sent. Return the number of messages pinned.
+    pub fn pin_last_turn(&mut self) -> usize {
+        self.chat.pin_last_turn()
+    }
+
+    // Attach a file to the next prompt, replacing any attachment with the same path. Return the
+    // number of tokens it adds.
+    pub fn attach(&mut self, attachment: attach::Attachment) -> usize {
+        let tokens = tokens::Counter::for_model(self.model()).message(&attachment.message());
+        self.attachments.retain(|a| a.path != attachment.path);
+        self.attachments.push(attachment);
+        tokens
+    }
+
+    // The paths of the files attached to the next prompt, with the tokens each adds.
+    pub fn attachments(&self) -> Vec<(String, usize)> {
+        let counter = tokens::Counter::for_model(self.model());
+        self.attachments
+            .iter()
+            .map(|a| (a.path.clone(), counter.message(&a.message())))
+            .collect()
+    }
+
+    // Remove the attachments that the path or glob gives, or all of them if it's not given. Return
+    // the paths removed.
+    pub fn drop_attachments(&mut self, pattern: Option<&str>) -> Vec<String> {
+        let (dropped, kept) = self
+            .attachments
+            .drain(..)
+            .partition(|a| pattern.is_none_or(|pattern| attach::matches(pattern, &a.path)));
+        self.attachments = kept;
+        dropped
+            .into_iter()
+            .map(|a: attach::Attachment| a.path)
+            .collect()
+    }
+
+    // Add the attached files and the user's text to the chat history and send it to the API. On
+    // success, return the model's reply. See `send` for how `on_delta` is called.
+    //
+    // If the request is refused as over budget, the text and files are taken back out of the
+    // history and the files are attached again, so that they aren't sent with every later prompt.
+    pub async fn chat<F: FnMut(&str) + Send>(&mut self, text: &str, on_delta: F) -> Result<Reply> {
+        let attachments = std::mem::take(&mut self.attachments);
+        for attachment in &attachments {
+            self.chat.add_message(attachment.message());
+        }
         self.chat.add_user_text(text);
-        self.send()
+        let result = self.send(on_delta).await;
+        // The new messages are still the last in the history unless a round of tool calls was
+        // added after them. Summarizing never reaches them, as they're part of the latest turn.
+        let refused = result.as_ref().is_err_and(|e| e.is::<budget::Overrun>())
+            && self.chat.messages.last().is_some_and(|m| m.role == "user");
+        if refused {
+            let len = self.chat.messages.len() - attachments.len() - 1;
+            self.chat.messages.truncate(len);
+            self.attachments = attachments;
+        }
+        result
+    }
+
+    // Keep one of several alternative replies, chosen by the user if there's a function to ask, and
+    // record the others as discarded. Return the reply kept, and whether it was shown while
+    // choosing.
+    fn keep_one(&mut self, mut replies: Vec<api::Message>) -> (api::Message, bool) {
+        // Replies that call tools aren't worth choosing between before the calls are made.
+        let choose = self
+            .choose
+            .as_ref()
+            .filter(|_| replies.iter().all(|m| m.tool_calls.is_none()));
+        let Some(choose) = choose else {
+            return (replies.swap_remove(0), false);
+        };
+        let texts: Vec<String> = replies
+            .iter()
+            .map(|m| m.text().unwrap_or_default().into_owned())
+            .collect();
+        let chosen = choose(&texts).min(replies.len() - 1);
+        let prompt = self
+            .chat
+            .messages
+            .iter()
+            .rfind(|m| m.role == "user")
+            .and_then(|m| m.text())
+            .unwrap_or_default()
+            .into_owned();
+        let reply = replies.remove(chosen);
+        self.chat
+            .discarded
+            .extend(replies.into_iter().map(|m| session::Discarded {
+                prompt: prompt.clone(),
+                reply: m.text().unwrap_or_default().into_owned(),
+            }));
+        (reply, true)
+    }
+
+    // Send the history once and return the model's message, which may call tools. Add the tokens
+    // used and their cost to the reply.
+    async fn request<F: FnMut(&str) + Send>(
+        &mut self,
+        on_delta: &mut F,
+        reply: &mut Reply,
+    ) -> Result<api::Message> {
+        let fitted = self.fit(&self.chat.messages);
+        self.check_budget(&fitted.messages)?;
+        let tools = self.tools.definitions();
+        let n = self.provider.client().options().n.unwrap_or(1);
+        let (gpt_message, usage) = if n > 1 {
+            let (replies, usage) = self
+                .provider
+                .send_choices(&fitted.messages, n, &tools)
+                .await?;
+            let (gpt_message, shown) = self.keep_one(replies);
+            if let (false, Some(text)) = (shown, gpt_message.text()) {
+                on_delta(&text);
+            }
+            (gpt_message, Some(usage))
+        } else if self.stream {
+            self.provider
+                .send_streaming(&fitted.messages, &tools, &mut *on_delta)
+                .await?
+        } else {
+            let (mut replies, usage) = self
+                .provider
+                .send_choices(&fitted.messages, 1, &tools)
+                .await?;
+            let gpt_message = replies.swap_remove(0);
+            if let Some(text) = gpt_message.text() {
+                on_delta(&text);
+            }
+            (gpt_message, Some(usage))
+        };
+        reply.dropped = fitted.dropped;
+        if let Some(usage) = usage {
+            let cost = self.record_usage(&usage);
+            match &mut reply.usage {
+                Some(total) => {
+                    total.prompt_tokens += usage.prompt_tokens;
+                    total.completion_tokens += usage.completion_tokens;
+                    total.total_tokens += usage.total_tokens;
+                    reply.cost = reply.cost.zip(cost).map(|(total, cost)| total + cost);
+                }
+                None => {
+                    reply.usage = Some(usage);
+                    reply.cost = cost;
+                }
+            }
+        }
+        Ok(gpt_message)
     }
 
     // Send the whole history to the API so that it can respond within the context of the
     // conversation. If a response is received, add it to the chat history. Else the chat history is
-    // not updated so the response and can be resent. On success, return the model's response text
-    // and the number of tokens used.
-    pub fn send(&mut self) -> Result<(String, u32)> {
-        let (gpt_message, tokens) = self.client.send(&self.chat.messages)?;
-
-        self.chat.add_message(gpt_message.clone());
+    // not updated so the response and can be resent. On success, return the model's reply.
+    //
+    // If the model calls tools, its message and the results of the calls are added to the history
+    // and the history is sent again, until the model gives its final answer. The calls in a message
+    // are executed in order, and their results are all sent together.
+    //
+    // When streaming, `on_delta` is called with each fragment of the reply's text as it arrives.
+    // Otherwise it's called once with the whole text. When several alternative replies are asked
+    // for, they aren't streamed, and the function choosing between them shows them instead.
+    pub async fn send<F: FnMut(&str) + Send>(&mut self, mut on_delta: F) -> Result<Reply> {
+        let mut reply = Reply {
+            usage: None,
+            cost: None,
+            dropped: 0,
+            summarized: self.summarize_if_needed().await?,
+        };
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let gpt_message = self.request(&mut on_delta, &mut reply).await?;
+            let calls = gpt_message.tool_calls.clone().unwrap_or_default();
+            if calls.is_empty() {
+                if gpt_message.content.is_none() {
+                    return Err(anyhow!("no content received"));
+                }
+                self.chat.add_message(gpt_message);
+                return Ok(reply);
+            }
+            // The message calling the tools is added with their results, so that a round cancelled
+            // while the tools are running leaves no calls without results in the history.
+            let mut results = Vec::with_capacity(calls.len());
+            for call in &calls {
+                results.push(self.tools.call(call).await);
+            }
+            self.chat.add_message(gpt_message);
+            for result in results {
+                self.chat.add_message(result);
+            }
+        }
+        Err(anyhow!(
+            "the model was still calling tools after {} rounds; enter `/retry` to let it continue",
+            MAX_TOOL_ROUNDS
+        ))
+    }
 
-        let text = gpt_message
-            .content
-            .clone()
-            .ok_or(anyhow!("no content received"))?;
-        Ok((text, tokens))
+    // The alternative replies that weren't kept, oldest first.
+    pub fn discarded(&self) -> &[session::Discarded] {
+        &self.chat.discarded
     }
 
     // Clear the chat history.
     pub fn clear(&mut self) {
         self.chat.clear();
     }
+
+    // Capture the chat history and the options it was held with so that they can be saved.
+    pub fn session(&self) -> session::Session {
+        session::Session::new(
+            self.provider.kind(),
+            self.provider.client().options().clone(),
+            self.chat.messages.clone(),
+            self.chat.pinned.iter().copied().collect(),
+            self.chat.discarded.clone(),
+        )
+    }
+
+    // Replace the chat history and options with those of a saved session. A system message at the
+    // start of the saved history becomes the system prompt kept when the chat is cleared.
+    //
+    // The requests keep going to the current provider's endpoint with its key, whatever the session
+    // was saved with, so a session file can't send the key elsewhere. A session held with a
+    // different provider is refused, as its model wouldn't be found.
+    pub fn restore(&mut self, session: session::Session) -> Result<()> {
+        if session.provider != self.provider.kind() {
+            bail!(
+                "the chat was held with the {} provider, not {}: select it with `--provider` or a \
+                 profile",
+                session.provider,
+                self.provider.kind()
+            );
+        }
+        self.chat.system_prompt = session
+            .messages
+            .first()
+            .filter(|m| m.role == "system" && !is_summary(m))
+            .and_then(|m| m.text())
+            .map(|text| text.into_owned());
+        self.chat.pinned = session
+            .pinned
+            .into_iter()
+            .filter(|&i| i < session.messages.len())
+            .collect();
+        self.chat.messages = session.messages;
+        self.chat.discarded = session.discarded;
+        let base_url = self.provider.client().options().base_url.clone();
+        self.provider.client_mut().set_options(client::Options {
+            base_url,
+            ..session.options
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry;
+    use serde_json::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // A provider whose every reply calls the `wait` tool.
+    struct CallingTools(client::Client);
+
+    fn tool_call() -> api::Message {
+        api::Message {
+            content: None,
+            tool_calls: Some(vec![api::ToolCall {
+                id: "call".to_string(),
+                kind: "function".to_string(),
+                function: api::FunctionCall {
+                    name: "wait".to_string(),
+                    arguments: "{}".to_string(),
+                },
+            }]),
+            ..api::Message::new("assistant", "")
+        }
+    }
+
+    impl provider::Provider for CallingTools {
+        fn kind(&self) -> provider::Kind {
+            provider::Kind::OpenAi
+        }
+        fn client(&self) -> &client::Client {
+            &self.0
+        }
+        fn client_mut(&mut self) -> &mut client::Client {
+            &mut self.0
+        }
+        fn send_choices<'a>(
+            &'a self,
+            _messages: &'a [api::Message],
+            _n: u32,
+            _tools: &'a [api::Tool],
+        ) -> provider::BoxFuture<'a, client::Result<(Vec<api::Message>, api::Usage)>> {
+            unimplemented!()
+        }
+        fn send_streaming<'a>(
+            &'a self,
+            _messages: &'a [api::Message],
+            _tools: &'a [api::Tool],
+            _on_delta: &'a mut (dyn FnMut(&str) + Send),
+        ) -> provider::BoxFuture<'a, client::Result<(api::Message, Option<api::Usage>)>> {
+            Box::pin(async { Ok((tool_call(), None)) })
+        }
+    }
+
+    // A tool that returns at once the first time it's called, and after that runs until it's
+    // cancelled. It counts the calls that saw the cancellation.
+    struct Wait {
+        calls: AtomicUsize,
+        cancelled: Arc<AtomicUsize>,
+    }
+
+    impl tools::Tool for Wait {
+        fn name(&self) -> &'static str {
+            "wait"
+        }
+        fn description(&self) -> &'static str {
+            "Wait."
+        }
+        fn parameters(&self) -> Value {
+            serde_json::json!({"type": "object", "properties": {}})
+        }
+        fn call(&self, _arguments: &Value, cancel: &tools::Cancel) -> Result<String> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Ok("done".to_string());
+            }
+            while !cancel.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            self.cancelled.fetch_add(1, Ordering::SeqCst);
+            Ok("cancelled".to_string())
+        }
+    }
+
+    fn chat_bot() -> ChatBot {
+        let options = client::Options::builder().model("gpt-4o").build();
+        let client = client::Client::new(options, retry::Policy::default()).unwrap();
+        ChatBot::new(
+            Box::new(CallingTools(client)),
+            true,
+            Some("Be brief.".to_string()),
+        )
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_tool_round_leaves_whole_rounds() {
+        let mut chat_bot = chat_bot();
+        let cancelled = Arc::new(AtomicUsize::new(0));
+        chat_bot.set_tools(tools::Registry::new(vec![Box::new(Wait {
+            calls: AtomicUsize::new(0),
+            cancelled: cancelled.clone(),
+        })]));
+
+        // Load the tokenizer first, which takes a while in debug builds.
+        chat_bot.prompt_tokens(None);
+        let chat = chat_bot.chat("hi", |_| {});
+        assert!(tokio::time::timeout(Duration::from_millis(200), chat)
+            .await
+            .is_err());
+
+        // The first round is kept whole, and the second, cancelled while its tool was running,
+        // is left out.
+        let roles: Vec<_> = chat_bot.chat.messages.iter().map(|m| &m.role).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "tool"]);
+        assert_eq!(
+            chat_bot.chat.messages[3].tool_call_id.as_deref(),
+            Some("call")
+        );
+
+        // The tool is told to stop.
+        for _ in 0..100 {
+            if cancelled.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(cancelled.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn a_prompt_refused_as_over_budget_is_taken_back() {
+        let mut chat_bot = chat_bot();
+        chat_bot.set_limits(budget::Limits {
+            request_tokens: budget::Limit {
+                soft: None,
+                hard: Some(1.0),
+            },
+            ..Default::default()
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "Some notes.").unwrap();
+        for attachment in attach::read(path.to_str().unwrap()).unwrap() {
+            chat_bot.attach(attachment);
+        }
+
+        let result = chat_bot.chat("hi", |_| {}).await;
+        assert!(result.is_err_and(|e| e.is::<budget::Overrun>()));
+        let roles: Vec<_> = chat_bot.chat.messages.iter().map(|m| &m.role).collect();
+        assert_eq!(roles, ["system"]);
+        assert_eq!(chat_bot.attachments().len(), 1);
+    }
 }
@@ -0,0 +1,174 @@
+use anyhow::Result;
+use chrono::{Datelike, Local};
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+use thousands::Separable;
+
+use crate::cost;
+
+// Budget limits guard against runaway spending, such as a long chat history sent again with every
+// request. Each limit can have a soft value, past which the user is asked to confirm the request,
+// and a hard value, past which the request is refused. Where there's no one to ask, as in one-shot
+// mode, soft limits are enforced like hard ones.
+//
+// Requests are checked before they're sent, using an estimate of their size and cost: the tokens of
+// the prompt plus the longest reply allowed by `max_tokens`, if set. Limits on spending include the
+// estimated cost of the request. Daily and monthly spending is read from the ledger, if there is
+// one; without it, only this session's spending counts towards them. See `cost.rs`.
+//
+// Example config:
+//
+//     [limits]
+//     request_tokens = { soft = 20000, hard = 100000 }
+//     session = { soft = 1.00 }
+//     day = { hard = 5.00 }
+//     month = { soft = 40.00, hard = 50.00 }
+
+// Limit is a soft and a hard limit on an amount. Either can be left unset.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    pub soft: Option<f64>,
+    pub hard: Option<f64>,
+}
+
+impl Limit {
+    fn is_set(&self) -> bool {
+        self.soft.is_some() || self.hard.is_some()
+    }
+
+    // The limit the amount is over, if any, with its value.
+    fn exceeded(&self, amount: f64) -> Option<(Severity, f64)> {
+        match (self.hard, self.soft) {
+            (Some(hard), _) if amount > hard => Some((Severity::Hard, hard)),
+            (_, Some(soft)) if amount > soft => Some((Severity::Soft, soft)),
+            _ => None,
+        }
+    }
+}
+
+// Limits are the limits on each request and on spending.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    // The tokens a single request may use.
+    pub request_tokens: Limit,
+    // The spending in US dollars of this session, today and this month.
+    pub session: Limit,
+    pub day: Limit,
+    pub month: Limit,
+}
+
+// Estimate is the expected size and cost of a request.
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    pub tokens: u64,
+    // The cost in US dollars, if the model's price is known.
+    pub cost: Option<f64>,
+}
+
+// Severity tells whether a request may still be sent after confirmation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Soft,
+    Hard,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Soft => "soft",
+            Severity::Hard => "hard",
+        })
+    }
+}
+
+// Overrun describes the limits that a request would exceed. It's the error returned for a request
+// that wasn't sent.
+#[derive(Debug, thiserror::Error)]
+pub struct Overrun {
+    // The most severe of the limits exceeded.
+    pub severity: Severity,
+    // What would exceed each limit.
+    pub reasons: Vec<String>,
+}
+
+impl fmt::Display for Overrun {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "over budget: {}", self.reasons.join("; "))
+    }
+}
+
+// Confirm is asked whether to send a request that's over a soft limit.
+pub type Confirm = Box<dyn Fn(&Overrun) -> bool + Send + Sync>;
+
+impl Limits {
+    // Check a request with the given estimate against the limits, given what this session has
+    // spent so far and the ledger of earlier spending, if any. Return the limits it would exceed,
+    // if any.
+    pub fn check(
+        &self,
+        estimate: &Estimate,
+        session_spent: f64,
+        ledger: Option<&Path>,
+    ) -> Result<Option<Overrun>> {
+        let mut exceeded = vec![];
+        if let Some((severity, limit)) = self.request_tokens.exceeded(estimate.tokens as f64) {
+            exceeded.push((
+                severity,
+                format!(
+                    "~{} tokens for this request ({} limit {})",
+                    estimate.tokens.separate_with_commas(),
+                    severity,
+                    (limit as u64).separate_with_commas()
+                ),
+            ));
+        }
+
+        let cost = estimate.cost.unwrap_or_default();
+        let mut spending = vec![("this session's", self.session, session_spent)];
+        if self.day.is_set() || self.month.is_set() {
+            let (mut day, mut month) = (session_spent, session_spent);
+            if let Some(ledger) = ledger {
+                let today = Local::now().date_naive();
+                (day, month) = (0.0, 0.0);
+                for entry in cost::ledger(ledger)? {
+                    let date = entry.time.date_naive();
+                    if date.year() == today.year() && date.month() == today.month() {
+                        let cost = entry.cost.unwrap_or_default();
+                        month += cost;
+                        if date == today {
+                            day += cost;
+                        }
+                    }
+                }
+            }
+            spending.push(("today's", self.day, day));
+            spending.push(("this month's", self.month, month));
+        }
+        for (period, limit, spent) in spending {
+            if let Some((severity, limit)) = limit.exceeded(spent + cost) {
+                exceeded.push((
+                    severity,
+                    format!(
+                        "{} spending would be {} ({} limit {})",
+                        period,
+                        cost::format(spent + cost),
+                        severity,
+                        cost::format(limit)
+                    ),
+                ));
+            }
+        }
+
+        Ok(exceeded
+            .iter()
+            .map(|(severity, _)| *severity)
+            .max()
+            .map(|severity| Overrun {
+                severity,
+                reasons: exceeded.into_iter().map(|(_, reason)| reason).collect(),
+            }))
+    }
+}
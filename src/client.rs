@@ -1,71 +1,532 @@
 use crate::api;
-use anyhow::{anyhow, Context, Result};
 use log::info;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
 
-pub const MODEL: &str = "gpt-3.5-turbo";
-const URL: &str = "https://api.openai.com/v1/chat/completions";
+use crate::retry;
 
-// Define a client that handles the HTTP requests and responses to and from the OpenAI API.
-pub struct Client {
-    auth_token: String,
-    client: reqwest::blocking::Client,
+pub const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+const DEFAULT_TEMPERATURE: f64 = 0.7;
+
+// How long to wait to connect to the API, and for a whole request including a streamed response.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(600);
+
+// The prefix of each server-sent event line that carries the event's data.
+const SSE_DATA_PREFIX: &str = "data:";
+
+// Options selects the model and endpoint to use and the parameters that control how the model
+// generates its replies. See `api::ChatRequest` for the meaning of each parameter. Parameters left
+// as None are omitted from requests so that the API's defaults apply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Options {
+    pub model: String,
+    // The URL that API paths such as `/chat/completions` are appended to.
+    pub base_url: String,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub stop: Option<Vec<String>>,
+    pub presence_penalty: Option<f64>,
+    pub frequency_penalty: Option<f64>,
+    pub logit_bias: Option<HashMap<u32, f64>>,
+    pub user: Option<String>,
+    // The number of alternative replies to generate for each prompt, for the user to choose
+    // between. Unlike the other parameters, it's only sent by `send_choices`.
+    pub n: Option<u32>,
 }
 
-impl Client {
-    pub fn new(auth_token: String) -> Self {
+impl Default for Options {
+    fn default() -> Self {
         Self {
-            auth_token,
-            client: reqwest::blocking::Client::new(),
+            model: DEFAULT_MODEL.to_string(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            temperature: Some(DEFAULT_TEMPERATURE),
+            top_p: None,
+            max_tokens: None,
+            stop: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            logit_bias: None,
+            user: None,
+            n: None,
         }
     }
+}
+
+impl Options {
+    // Start building options from the defaults.
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder {
+            options: Self::default(),
+        }
+    }
+}
+
+// OptionsBuilder sets options one at a time, starting from the defaults. The optional parameters
+// can be given as values or as Option, so that None unsets a default.
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.options.model = model.into();
+        self
+    }
+
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.options.base_url = base_url.into();
+        self
+    }
+
+    pub fn temperature(mut self, temperature: impl Into<Option<f64>>) -> Self {
+        self.options.temperature = temperature.into();
+        self
+    }
+
+    pub fn top_p(mut self, top_p: impl Into<Option<f64>>) -> Self {
+        self.options.top_p = top_p.into();
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: impl Into<Option<u32>>) -> Self {
+        self.options.max_tokens = max_tokens.into();
+        self
+    }
+
+    pub fn stop(mut self, stop: impl Into<Option<Vec<String>>>) -> Self {
+        self.options.stop = stop.into();
+        self
+    }
+
+    pub fn presence_penalty(mut self, presence_penalty: impl Into<Option<f64>>) -> Self {
+        self.options.presence_penalty = presence_penalty.into();
+        self
+    }
 
-    // Send the chat history to the API. Return the reply message and the number of tokens used in
-    // the response. Log the full request and response.
-    pub fn send(&self, messages: &Vec<api::Message>) -> Result<(api::Message, u32)> {
-        let request = api::ChatRequest {
-            model: MODEL.to_string(),
-            messages: messages.clone(),
-            temperature: Some(0.7),
-            ..Default::default()
+    pub fn frequency_penalty(mut self, frequency_penalty: impl Into<Option<f64>>) -> Self {
+        self.options.frequency_penalty = frequency_penalty.into();
+        self
+    }
+
+    pub fn logit_bias(mut self, logit_bias: impl Into<Option<HashMap<u32, f64>>>) -> Self {
+        self.options.logit_bias = logit_bias.into();
+        self
+    }
+
+    pub fn user(mut self, user: impl Into<Option<String>>) -> Self {
+        self.options.user = user.into();
+        self
+    }
+
+    pub fn n(mut self, n: impl Into<Option<u32>>) -> Self {
+        self.options.n = n.into();
+        self
+    }
+
+    pub fn build(self) -> Options {
+        self.options
+    }
+}
+
+// The error codes and types the API uses for the errors that are reported as their own kinds.
+const CONTEXT_LENGTH_EXCEEDED: &str = "context_length_exceeded";
+const INSUFFICIENT_QUOTA: &str = "insufficient_quota";
+
+// Error is the kind of failure of a request to the API. The API's own description of the error,
+// if it sent one, is kept so that it can be shown to the user.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // The API key is missing, invalid, or not permitted to use the model.
+    #[error("authentication failed (code: {status}): {}", .error.message)]
+    Auth {
+        status: StatusCode,
+        error: api::ApiError,
+    },
+    // Too many requests or tokens were sent in too short a time.
+    #[error("rate limit reached: {}", .error.message)]
+    RateLimit { error: api::ApiError },
+    // The account has run out of credit.
+    #[error("quota exceeded: {}", .error.message)]
+    QuotaExceeded { error: api::ApiError },
+    // The chat history and the reply don't fit in the model's context window.
+    #[error("context length exceeded: {}", .error.message)]
+    ContextLengthExceeded { error: api::ApiError },
+    // The request was rejected, for example for an unknown model or an invalid parameter.
+    #[error("invalid request (code: {status}): {}", .error.message)]
+    InvalidRequest {
+        status: StatusCode,
+        error: api::ApiError,
+    },
+    // The API failed to handle a valid request.
+    #[error("server error (code: {status}): {}", .error.message)]
+    Server {
+        status: StatusCode,
+        error: api::ApiError,
+    },
+    // The API failed after it had started streaming the reply, and said so in an event of the
+    // stream. There's no status for it: the response had already succeeded.
+    #[error("error in the response stream: {}", describe(.error))]
+    Stream { error: api::ApiError },
+    // The request couldn't be sent or the response couldn't be received. This is an error with the
+    // reqwest library or the network, not the API.
+    #[error("error sending request: {0}")]
+    Transport(#[from] reqwest::Error),
+    // The response couldn't be understood.
+    #[error("unexpected response: {0}")]
+    Response(String),
+}
+
+impl Error {
+    // Classify an unsuccessful response by its status and the error the API described in its body.
+    pub(crate) fn from_response(status: StatusCode, body: &str) -> Self {
+        let error = match serde_json::from_str::<api::ErrorResponse>(body) {
+            Ok(r) => r.error,
+            Err(_) => api::ApiError {
+                message: if body.trim().is_empty() {
+                    status
+                        .canonical_reason()
+                        .unwrap_or("no details")
+                        .to_string()
+                } else {
+                    body.trim().to_string()
+                },
+                kind: None,
+                param: None,
+                code: None,
+            },
         };
+        let is =
+            |name: &str| error.code.as_deref() == Some(name) || error.kind.as_deref() == Some(name);
 
-        info!("Request: {:#?}", &request);
-
-        let res = self
-            .client
-            .post(URL)
-            .bearer_auth(&self.auth_token)
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send();
-
-        let resp = match res {
-            Ok(resp) => resp,
-            Err(e) => {
-                // This is an error with the reqwest library or the network, not the API.
-                return Err(anyhow!("error sending request: {}", e));
-            }
+        if is(CONTEXT_LENGTH_EXCEEDED) {
+            Error::ContextLengthExceeded { error }
+        } else if is(INSUFFICIENT_QUOTA) {
+            Error::QuotaExceeded { error }
+        } else if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            Error::Auth { status, error }
+        } else if status == StatusCode::TOO_MANY_REQUESTS {
+            Error::RateLimit { error }
+        } else if status.is_client_error() {
+            Error::InvalidRequest { status, error }
+        } else {
+            Error::Server { status, error }
+        }
+    }
+
+    // Read the error the API reported in an event of a response stream.
+    pub(crate) fn from_stream_event(data: &str) -> Self {
+        let error = match serde_json::from_str::<api::ErrorResponse>(data) {
+            Ok(r) => r.error,
+            Err(_) => api::ApiError {
+                message: data.trim().to_string(),
+                kind: None,
+                param: None,
+                code: None,
+            },
         };
+        Error::Stream { error }
+    }
 
-        info!("Response: {:#?}", &resp);
+    // Whether the request is worth retrying: it may succeed if it's sent again later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::RateLimit { .. } => true,
+            Error::Server { status, .. } => retry::is_retryable_status(*status),
+            Error::Transport(e) => retry::is_retryable_error(e),
+            _ => false,
+        }
+    }
 
-        // Check for server errors.
-        if !resp.status().is_success() {
-            return Err(anyhow!(
-                "unsuccessful API request (code: {})",
-                resp.status()
-            ));
+    // The API's description of the error, if it sent one.
+    pub fn api_error(&self) -> Option<&api::ApiError> {
+        match self {
+            Error::Auth { error, .. }
+            | Error::RateLimit { error }
+            | Error::QuotaExceeded { error }
+            | Error::ContextLengthExceeded { error }
+            | Error::InvalidRequest { error, .. }
+            | Error::Server { error, .. }
+            | Error::Stream { error } => Some(error),
+            Error::Transport(_) | Error::Response(_) => None,
         }
+    }
+}
+
+// The error's type, if the API gave one, and its message.
+fn describe(error: &api::ApiError) -> String {
+    match &error.kind {
+        Some(kind) => format!("{}: {}", kind, error.message),
+        None => error.message.clone(),
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// Define a client that handles the HTTP requests and responses to and from the API. Requests are
+// async, so several can be in flight at once and a request is cancelled by dropping its future. It
+// needs a tokio runtime with time and IO enabled. See `blocking.rs` for running it synchronously.
+// What's sent depends on the provider. See `provider.rs`.
+pub struct Client {
+    options: Options,
+    retry_policy: retry::Policy,
+    // Called before each retry so that the user can be told about it.
+    retry_notifier: Option<retry::Notifier>,
+    client: reqwest::Client,
+}
+
+impl Client {
+    pub fn new(options: Options, retry_policy: retry::Policy) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            options,
+            retry_policy,
+            retry_notifier: None,
+            client,
+        })
+    }
+
+    // Set the function called before each retry of a failed request.
+    pub fn set_retry_notifier(&mut self, notifier: retry::Notifier) {
+        self.retry_notifier = Some(notifier);
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: Options) {
+        self.options = options;
+    }
+
+    // The URL of the given API path, relative to the base URL.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.options.base_url.trim_end_matches('/'), path)
+    }
+
+    // Post the request as JSON to the URL, with the given headers, and return the response if it
+    // was successful. Rate limits, server errors and network timeouts are retried according to the
+    // retry policy. Log the full request and the response headers.
+    pub async fn post<T: Serialize + fmt::Debug + Sync>(
+        &self,
+        url: &str,
+        headers: &[(&str, String)],
+        request: &T,
+    ) -> Result<reqwest::Response> {
+        info!("Request: {:#?}", request);
+
+        let max_attempts = self.retry_policy.max_retries + 1;
+        let mut attempt = 1;
+        loop {
+            let mut builder = self
+                .client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .json(request);
+            for (name, value) in headers {
+                builder = builder.header(*name, value);
+            }
+            let res = builder.send().await;
+
+            let (error, server_delay) = match res {
+                Ok(resp) if resp.status().is_success() => {
+                    info!("Response: {:#?}", &resp);
+                    return Ok(resp);
+                }
+                Ok(resp) => {
+                    info!("Response: {:#?}", &resp);
+                    let status = resp.status();
+                    let server_delay = retry::server_delay(status, resp.headers());
+                    let body = resp.text().await.unwrap_or_default();
+                    info!("Response body: {}", &body);
+                    (Error::from_response(status, &body), server_delay)
+                }
+                Err(e) => (Error::Transport(e), None),
+            };
+            if !error.is_retryable() || attempt == max_attempts {
+                return Err(error);
+            }
+
+            let delay = match server_delay {
+                // Waiting longer than the policy allows would leave the user without feedback.
+                Some(delay) if delay > self.retry_policy.max_delay => {
+                    info!("Not retrying: the server asked to wait {:?}", delay);
+                    return Err(error);
+                }
+                Some(delay) => delay,
+                None => self.retry_policy.backoff(attempt),
+            };
+            let notice = retry::Notice {
+                delay,
+                attempt: attempt + 1,
+                max_attempts,
+                reason: error.to_string(),
+            };
+            info!("Retrying: {:?}", &notice);
+            if let Some(notify) = &self.retry_notifier {
+                notify(&notice);
+            }
+            tokio::time::sleep(notice.delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+// Read and deserialize the JSON body of a successful response. Log the body.
+pub async fn read_json<T: DeserializeOwned>(resp: reqwest::Response) -> Result<T> {
+    let text = resp.text().await?;
+    info!("Response body: {}", &text);
+    serde_json::from_str(&text).map_err(|e| Error::Response(e.to_string()))
+}
+
+// Read the body of a successful response as server-sent events, calling `on_data` with the data of
+// each event until it returns true or the stream ends. Log each event's data.
+pub async fn read_events<F>(mut resp: reqwest::Response, mut on_data: F) -> Result<()>
+where
+    F: FnMut(&str) -> Result<bool>,
+{
+    // Events are separated by blank lines and may include comments or other fields. Only the data
+    // lines carry the payloads.
+    let mut on_line = |line: &str| match line.strip_prefix(SSE_DATA_PREFIX) {
+        Some(data) => {
+            info!("Event data: {}", data.trim());
+            on_data(data.trim())
+        }
+        None => Ok(false),
+    };
+
+    // The body arrives in chunks that needn't end at the ends of lines, so the partial last line is
+    // kept until the rest of it arrives.
+    let mut buffer = vec![];
+    while let Some(bytes) = resp
+        .chunk()
+        .await
+        .map_err(|e| Error::Response(format!("error reading response stream: {}", e)))?
+    {
+        buffer.extend_from_slice(&bytes);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            if on_line(String::from_utf8_lossy(&line).trim_end())? {
+                return Ok(());
+            }
+        }
+    }
+    // The last line may not end with a newline.
+    on_line(String::from_utf8_lossy(&buffer).trim_end())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Serve a single response whose body is written in the given chunks, pausing between them so
+    // that they arrive separately. Returns the server's URL.
+    fn serve(chunks: &[&'static str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let chunks = chunks.to_vec();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut byte = [0; 1];
+            while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+                request.push(byte[0]);
+            }
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                      Connection: close\r\n\r\n",
+                )
+                .unwrap();
+            for chunk in chunks {
+                stream.write_all(chunk.as_bytes()).unwrap();
+                stream.flush().unwrap();
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        url
+    }
+
+    // Request the served response, bypassing any proxy set in the environment.
+    async fn get(url: String) -> reqwest::Response {
+        reqwest::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(url)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    // The data of the events served in the chunks, read until `[DONE]` or the end of the body.
+    async fn events(chunks: &[&'static str]) -> Vec<String> {
+        let resp = get(serve(chunks)).await;
+        let mut data = vec![];
+        read_events(resp, |d| {
+            data.push(d.to_string());
+            Ok(d == "[DONE]")
+        })
+        .await
+        .unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn events_split_across_chunks_are_joined() {
+        let data = events(&[
+            "data: {\"a\"",
+            ":1}\r\n\r\nda",
+            "ta: {\"b\":2}\n\n: a comment\nevent: ping\n\n",
+        ])
+        .await;
+        assert_eq!(data, ["{\"a\":1}", "{\"b\":2}"]);
+    }
+
+    #[tokio::test]
+    async fn reading_stops_at_done() {
+        let data = events(&["data: 1\n\ndata: [DONE]\n\n", "data: 2\n\n"]).await;
+        assert_eq!(data, ["1", "[DONE]"]);
+    }
+
+    #[tokio::test]
+    async fn the_last_line_needs_no_newline() {
+        let data = events(&["data: 1\n\n", "data: 2"]).await;
+        assert_eq!(data, ["1", "2"]);
+    }
 
-        // Extract and deserialize the model's message.
-        let text = resp.text()?;
-        let r: api::ChatResponse = serde_json::from_str(&text)?;
-        let reply = r
-            .choices
-            .first()
-            .context("no first choice")?
-            .message
-            .clone();
-        Ok((reply, r.usage.total_tokens))
+    #[tokio::test]
+    async fn error_events_end_the_stream() {
+        let resp = get(serve(&[
+            "data: {\"error\":{\"type\":\"server_error\",\"message\":\"overloaded\"}}\n\n",
+            "data: 1\n\n",
+        ]))
+        .await;
+        let mut count = 0;
+        let error = read_events(resp, |data| {
+            count += 1;
+            Err(Error::from_stream_event(data))
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(count, 1);
+        assert_eq!(
+            error.to_string(),
+            "error in the response stream: server_error: overloaded"
+        );
+        assert!(!error.is_retryable());
     }
 }
@@ -0,0 +1,848 @@
+use anyhow::{anyhow, bail, Result};
+use std::path::Path;
+use thousands::Separable;
+
+use crate::input;
+use crate::persona;
+use crate::repl;
+use chatgpt_api_cli::attach;
+use chatgpt_api_cli::blocking;
+use chatgpt_api_cli::bot;
+use chatgpt_api_cli::client;
+use chatgpt_api_cli::cost;
+use chatgpt_api_cli::session;
+
+// Lines starting with `/` run commands, such as `/save <name>`. Each command implements `Command`
+// and is added to the registry in `Registry::new`, which finds the command for a line, runs it with
+// the rest of the line as its argument, and generates the help text from the commands' own.
+
+// Flow says whether the chat goes on after a command.
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+// Context is what a command can act on.
+pub struct Context<'a> {
+    pub chat_bot: &'a mut blocking::ChatBot,
+    // Whether replies' Markdown is rendered.
+    pub render: bool,
+    pub registry: &'a Registry,
+}
+
+// UsageError is returned by a command given the wrong argument. The registry shows the command's
+// usage.
+#[derive(Debug, thiserror::Error)]
+#[error("wrong argument")]
+struct UsageError;
+
+// Command is a slash command.
+pub trait Command: Send + Sync {
+    // The command's name, including the slash.
+    fn name(&self) -> &'static str;
+
+    // Other names for the command.
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    // The command's argument for the help text, such as `<name>` or `[text]`, if it takes one.
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    // A one-line description of the command for the help text.
+    fn help(&self) -> &'static str;
+
+    // The values that complete the command's argument, given its start.
+    fn complete(&self, _arg: &str) -> Vec<String> {
+        vec![]
+    }
+
+    // Run the command with the rest of the line, trimmed, as its argument.
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow>;
+}
+
+// Registry holds the commands.
+pub struct Registry {
+    commands: Vec<Box<dyn Command>>,
+}
+
+impl Registry {
+    // Create a registry of the built-in commands.
+    pub fn new() -> Self {
+        let mut registry = Self { commands: vec![] };
+        registry.register(Box::new(Help));
+        registry.register(Box::new(Quit));
+        registry.register(Box::new(Retry));
+        registry.register(Box::new(Clear));
+        registry.register(Box::new(Edit));
+        registry.register(Box::new(AddFiles));
+        registry.register(Box::new(Image));
+        registry.register(Box::new(DropFiles));
+        registry.register(Box::new(Model));
+        registry.register(Box::new(Set));
+        registry.register(Box::new(System));
+        registry.register(Box::new(Persona));
+        registry.register(Box::new(Save));
+        registry.register(Box::new(Load));
+        registry.register(Box::new(Pin));
+        registry.register(Box::new(Summarize));
+        registry.register(Box::new(Summary));
+        registry.register(Box::new(Cost));
+        registry.register(Box::new(Alternatives));
+        registry.register(Box::new(Discarded));
+        registry
+    }
+
+    // Add a command. Commands are listed in the help text in the order they're added.
+    pub fn register(&mut self, command: Box<dyn Command>) {
+        self.commands.push(command);
+    }
+
+    // The command with the given name or alias.
+    pub fn find(&self, name: &str) -> Option<&dyn Command> {
+        self.commands
+            .iter()
+            .find(|c| c.name() == name || c.aliases().contains(&name))
+            .map(|c| c.as_ref())
+    }
+
+    // The names of the commands, not including aliases.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.commands.iter().map(|c| c.name()).collect()
+    }
+
+    // The help text listing the commands.
+    pub fn help(&self) -> String {
+        let width = self
+            .commands
+            .iter()
+            .map(|c| synopsis(c.as_ref()).len())
+            .max()
+            .unwrap_or(0);
+        let mut help = "Enter text to send it to the model. Press Alt-Enter or type `\"\"\"` to \
+            write several lines, and start with `//` to send text starting with `/`. Commands:"
+            .to_string();
+        for command in &self.commands {
+            help.push_str(&format!(
+                "\n  {:width$}  {}",
+                synopsis(command.as_ref()),
+                command.help()
+            ));
+        }
+        help
+    }
+
+    // Run the command on the line, which starts with the command's name. Errors are shown to the
+    // user.
+    pub fn run(&self, context: &mut Context, line: &str) -> Flow {
+        let (name, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let Some(command) = self.find(name) else {
+            println!(
+                "  [Unknown command: {}. Enter `/help` to list the commands]",
+                name
+            );
+            return Flow::Continue;
+        };
+        match command.run(context, arg.trim()) {
+            Ok(flow) => flow,
+            Err(e) if e.is::<UsageError>() => {
+                println!("  [Usage: {}]", synopsis(command));
+                Flow::Continue
+            }
+            Err(e) => {
+                println!("  [Error: {:#}]", e);
+                Flow::Continue
+            }
+        }
+    }
+}
+
+// The command's name followed by its argument, if it takes one.
+fn synopsis(command: &dyn Command) -> String {
+    format!("{} {}", command.name(), command.usage())
+        .trim_end()
+        .to_string()
+}
+
+// Restore the named session into the chatbot.
+pub fn load_session(chat_bot: &mut bot::ChatBot, name: &str) -> Result<()> {
+    let session = session::load(name)?;
+    let count = session.messages.len();
+    chat_bot.restore(session)?;
+    println!(
+        "  [Loaded chat `{}` with {} messages for {}]",
+        name,
+        count,
+        chat_bot.model()
+    );
+    Ok(())
+}
+
+struct Help;
+
+impl Command for Help {
+    fn name(&self) -> &'static str {
+        "/help"
+    }
+    fn usage(&self) -> &'static str {
+        "[command]"
+    }
+    fn help(&self) -> &'static str {
+        "List the commands, or describe one."
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        if arg.is_empty() {
+            println!("{}", context.registry.help());
+            return Ok(Flow::Continue);
+        }
+        let name = format!("/{}", arg.trim_start_matches('/'));
+        let command = context
+            .registry
+            .find(&name)
+            .ok_or(anyhow!("unknown command: {}", name))?;
+        println!("  {}", synopsis(command));
+        println!("    {}", command.help());
+        if !command.aliases().is_empty() {
+            println!("    Also: {}", command.aliases().join(", "));
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+struct Quit;
+
+impl Command for Quit {
+    fn name(&self) -> &'static str {
+        "/quit"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["/exit", "/q"]
+    }
+    fn help(&self) -> &'static str {
+        "Exit."
+    }
+    fn run(&self, _context: &mut Context, _arg: &str) -> Result<Flow> {
+        println!("  [Exiting]");
+        Ok(Flow::Quit)
+    }
+}
+
+struct Retry;
+
+impl Command for Retry {
+    fn name(&self) -> &'static str {
+        "/retry"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["/r"]
+    }
+    fn help(&self) -> &'static str {
+        "Resend the chat, such as after an error."
+    }
+    fn run(&self, context: &mut Context, _arg: &str) -> Result<Flow> {
+        println!(
+            "  [Resending chat to {} (~{} prompt tokens)...]",
+            context.chat_bot.model(),
+            context.chat_bot.prompt_tokens(None).separate_with_commas()
+        );
+        repl::print_response(context.chat_bot, context.render, |chat_bot, on_delta| {
+            chat_bot.send(on_delta)
+        });
+        Ok(Flow::Continue)
+    }
+}
+
+struct Clear;
+
+impl Command for Clear {
+    fn name(&self) -> &'static str {
+        "/clear"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["/c"]
+    }
+    fn help(&self) -> &'static str {
+        "Clear the chat history, keeping the system prompt."
+    }
+    fn run(&self, context: &mut Context, _arg: &str) -> Result<Flow> {
+        println!("  [Clearing chat history]");
+        context.chat_bot.clear();
+        Ok(Flow::Continue)
+    }
+}
+
+struct Edit;
+
+impl Command for Edit {
+    fn name(&self) -> &'static str {
+        "/edit"
+    }
+    fn help(&self) -> &'static str {
+        "Write the text to send in $VISUAL or $EDITOR."
+    }
+    fn run(&self, context: &mut Context, _arg: &str) -> Result<Flow> {
+        match input::compose()? {
+            Some(text) => {
+                println!("{}", text);
+                repl::send_text(context.chat_bot, context.render, &text);
+            }
+            None => println!("  [Nothing to send]"),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+struct AddFiles;
+
+impl Command for AddFiles {
+    fn name(&self) -> &'static str {
+        "/add"
+    }
+    fn usage(&self) -> &'static str {
+        "[path|glob]"
+    }
+    fn help(&self) -> &'static str {
+        "List the files attached to the next prompt, or attach more. `@path` in a prompt attaches \
+         one too."
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        if !arg.is_empty() {
+            repl::add_attachments(context.chat_bot, attach::read(arg)?);
+            return Ok(Flow::Continue);
+        }
+        let attachments = context.chat_bot.attachments();
+        if attachments.is_empty() {
+            println!(
+                "  [No files are attached. Enter `/add <path|glob>` or mention `@path` in a prompt]"
+            );
+            return Ok(Flow::Continue);
+        }
+        for (path, tokens) in &attachments {
+            println!("  {}  ({} tokens)", path, tokens.separate_with_commas());
+        }
+        println!(
+            "  [{} {}, {} tokens, to send with the next prompt]",
+            attachments.len(),
+            if attachments.len() == 1 {
+                "file"
+            } else {
+                "files"
+            },
+            attachments
+                .iter()
+                .map(|(_, tokens)| tokens)
+                .sum::<usize>()
+                .separate_with_commas()
+        );
+        Ok(Flow::Continue)
+    }
+}
+
+struct Image;
+
+impl Command for Image {
+    fn name(&self) -> &'static str {
+        "/image"
+    }
+    fn usage(&self) -> &'static str {
+        "<path>"
+    }
+    fn help(&self) -> &'static str {
+        "Attach a PNG or JPEG image to the next prompt, for models that can see images."
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        if arg.is_empty() {
+            return Err(UsageError.into());
+        }
+        let image = attach::image(Path::new(arg))?;
+        repl::add_attachments(context.chat_bot, vec![image]);
+        Ok(Flow::Continue)
+    }
+}
+
+struct DropFiles;
+
+impl Command for DropFiles {
+    fn name(&self) -> &'static str {
+        "/drop"
+    }
+    fn usage(&self) -> &'static str {
+        "[path|glob]"
+    }
+    fn help(&self) -> &'static str {
+        "Remove the files attached to the next prompt, or those given."
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        let pattern = (!arg.is_empty()).then_some(arg);
+        let dropped = context.chat_bot.drop_attachments(pattern);
+        match (dropped.is_empty(), pattern) {
+            (true, Some(pattern)) => bail!("no attached files match {}", pattern),
+            (true, None) => println!("  [No files are attached]"),
+            (false, _) => println!("  [Dropped {}]", dropped.join(", ")),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+struct Model;
+
+impl Command for Model {
+    fn name(&self) -> &'static str {
+        "/model"
+    }
+    fn usage(&self) -> &'static str {
+        "[name]"
+    }
+    fn help(&self) -> &'static str {
+        "Show the model, or switch to another."
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        if !arg.is_empty() {
+            let mut options = context.chat_bot.options().clone();
+            options.model = arg.to_string();
+            context.chat_bot.set_options(options);
+        }
+        println!("  [Model: {}]", context.chat_bot.model());
+        Ok(Flow::Continue)
+    }
+}
+
+// The options that `/set` can change.
+const SETTABLE_OPTIONS: &[&str] = &[
+    "temperature",
+    "top_p",
+    "max_tokens",
+    "presence_penalty",
+    "frequency_penalty",
+    "stop",
+    "user",
+];
+
+// The most stop sequences the API accepts.
+const MAX_STOP_SEQUENCES: usize = 4;
+
+// Show an option's value, or `default` if it's unset.
+fn show<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map_or("default".to_string(), |v| v.to_string())
+}
+
+// Parse an option's value. `none` unsets it.
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<Option<T>> {
+    if value == "none" {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| anyhow!("invalid value for {}: {}", name, value))
+}
+
+// Parse the comma-separated stop sequences. `none`, or no value at all, unsets them.
+fn parse_stop(value: &str) -> Result<Option<Vec<String>>> {
+    if value.is_empty() || value == "none" {
+        return Ok(None);
+    }
+    let sequences: Vec<String> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect();
+    if sequences.len() > MAX_STOP_SEQUENCES {
+        bail!(
+            "too many stop sequences: {} (at most {})",
+            sequences.len(),
+            MAX_STOP_SEQUENCES
+        );
+    }
+    Ok((!sequences.is_empty()).then_some(sequences))
+}
+
+struct Set;
+
+impl Set {
+    // The values of the settable options.
+    fn show(options: &client::Options) {
+        println!("  temperature        {}", show(&options.temperature));
+        println!("  top_p              {}", show(&options.top_p));
+        println!("  max_tokens         {}", show(&options.max_tokens));
+        println!("  presence_penalty   {}", show(&options.presence_penalty));
+        println!("  frequency_penalty  {}", show(&options.frequency_penalty));
+        println!(
+            "  stop               {}",
+            show(&options.stop.as_ref().map(|s| s.join(", ")))
+        );
+        println!("  user               {}", show(&options.user));
+    }
+}
+
+impl Command for Set {
+    fn name(&self) -> &'static str {
+        "/set"
+    }
+    fn usage(&self) -> &'static str {
+        "[option value]"
+    }
+    fn help(&self) -> &'static str {
+        "Show the sampling options, or set one, such as `/set temperature 0.2`. `none` unsets it. \
+         `stop` takes up to 4 comma-separated sequences; without a value it clears them."
+    }
+    fn complete(&self, arg: &str) -> Vec<String> {
+        SETTABLE_OPTIONS
+            .iter()
+            .filter(|o| o.starts_with(arg))
+            .map(|o| o.to_string())
+            .collect()
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        let mut options = context.chat_bot.options().clone();
+        if arg.is_empty() {
+            Self::show(&options);
+            return Ok(Flow::Continue);
+        }
+        let (name, value) = match arg.split_once(char::is_whitespace) {
+            Some((name, value)) => (name, value.trim()),
+            None if arg == "stop" => (arg, ""),
+            None => return Err(UsageError.into()),
+        };
+        match name {
+            "temperature" => options.temperature = parse(name, value)?,
+            "top_p" => options.top_p = parse(name, value)?,
+            "max_tokens" => options.max_tokens = parse(name, value)?,
+            "presence_penalty" => options.presence_penalty = parse(name, value)?,
+            "frequency_penalty" => options.frequency_penalty = parse(name, value)?,
+            "stop" => options.stop = parse_stop(value)?,
+            "user" => options.user = parse(name, value)?,
+            _ => bail!(
+                "unknown option `{}` (available: {})",
+                name,
+                SETTABLE_OPTIONS.join(", ")
+            ),
+        }
+        let value = match name {
+            "stop" => show(&options.stop.as_ref().map(|s| s.join(", "))),
+            _ => value.to_string(),
+        };
+        context.chat_bot.set_options(options);
+        println!("  [Set {} to {}]", name, value);
+        Ok(Flow::Continue)
+    }
+}
+
+struct Alternatives;
+
+impl Command for Alternatives {
+    fn name(&self) -> &'static str {
+        "/alternatives"
+    }
+    fn usage(&self) -> &'static str {
+        "[n]"
+    }
+    fn help(&self) -> &'static str {
+        "Show how many replies are generated for each prompt, or set it. Choose which one to keep."
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        if !arg.is_empty() {
+            let n: u32 = arg
+                .parse()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or(anyhow!("invalid number of replies: {}", arg))?;
+            let mut options = context.chat_bot.options().clone();
+            options.n = (n > 1).then_some(n);
+            context.chat_bot.set_options(options);
+        }
+        println!(
+            "  [Replies per prompt: {}]",
+            context.chat_bot.options().n.unwrap_or(1)
+        );
+        Ok(Flow::Continue)
+    }
+}
+
+// The first line of the text, shortened to about the given number of characters.
+fn preview(text: &str, len: usize) -> String {
+    let line = text.trim().lines().next().unwrap_or_default();
+    if line.chars().count() <= len && !text.trim().contains('\n') {
+        return line.to_string();
+    }
+    format!("{}…", line.chars().take(len).collect::<String>().trim_end())
+}
+
+struct Discarded;
+
+impl Command for Discarded {
+    fn name(&self) -> &'static str {
+        "/discarded"
+    }
+    fn usage(&self) -> &'static str {
+        "[number]"
+    }
+    fn help(&self) -> &'static str {
+        "List the alternative replies that weren't kept, or show one."
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        let discarded = context.chat_bot.discarded();
+        if discarded.is_empty() {
+            println!(
+                "  [No replies have been discarded. Enter `/alternatives <n>` to choose between \
+                 replies]"
+            );
+            return Ok(Flow::Continue);
+        }
+        if arg.is_empty() {
+            for (i, d) in discarded.iter().enumerate() {
+                println!(
+                    "  {:>3}. {}  [reply to: {}]",
+                    i + 1,
+                    preview(&d.reply, 50),
+                    preview(&d.prompt, 30)
+                );
+            }
+            return Ok(Flow::Continue);
+        }
+        let d = arg
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| discarded.get(i.checked_sub(1)?))
+            .ok_or(anyhow!(
+                "no discarded reply {}: enter a number from 1 to {}",
+                arg,
+                discarded.len()
+            ))?;
+        println!("  [Reply to: {}]\n{}", preview(&d.prompt, 60), d.reply);
+        Ok(Flow::Continue)
+    }
+}
+
+struct System;
+
+impl Command for System {
+    fn name(&self) -> &'static str {
+        "/system"
+    }
+    fn usage(&self) -> &'static str {
+        "[text]"
+    }
+    fn help(&self) -> &'static str {
+        "Show the system prompt, or replace it."
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        if !arg.is_empty() {
+            context.chat_bot.set_system_prompt(arg);
+            println!("  [Set the system prompt]");
+            return Ok(Flow::Continue);
+        }
+        match context.chat_bot.system_prompt() {
+            Some(prompt) => println!("  [System prompt:]\n{}", prompt),
+            None => println!("  [There's no system prompt. Enter `/system <text>` to set one]"),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+struct Persona;
+
+impl Command for Persona {
+    fn name(&self) -> &'static str {
+        "/persona"
+    }
+    fn usage(&self) -> &'static str {
+        "[name]"
+    }
+    fn help(&self) -> &'static str {
+        "List the personas, or use one's system prompt."
+    }
+    fn complete(&self, arg: &str) -> Vec<String> {
+        // A directory that can't be read just has nothing to offer.
+        let names = persona::list().unwrap_or_default();
+        names.into_iter().filter(|n| n.starts_with(arg)).collect()
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        if arg.is_empty() {
+            let names = persona::list()?;
+            if names.is_empty() {
+                println!("  [There are no personas]");
+            } else {
+                println!("  [Personas: {}]", names.join(", "));
+            }
+            return Ok(Flow::Continue);
+        }
+        let prompt = persona::load(arg)?;
+        context.chat_bot.set_system_prompt(&prompt);
+        println!("  [Set the system prompt from persona `{}`]", arg);
+        Ok(Flow::Continue)
+    }
+}
+
+// The saved sessions whose names start with the argument.
+fn complete_session(arg: &str) -> Vec<String> {
+    let names = session::list().unwrap_or_default();
+    names.into_iter().filter(|n| n.starts_with(arg)).collect()
+}
+
+struct Save;
+
+impl Command for Save {
+    fn name(&self) -> &'static str {
+        "/save"
+    }
+    fn usage(&self) -> &'static str {
+        "<name>"
+    }
+    fn help(&self) -> &'static str {
+        "Save the chat history, model and parameters."
+    }
+    fn complete(&self, arg: &str) -> Vec<String> {
+        complete_session(arg)
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        if arg.is_empty() {
+            return Err(UsageError.into());
+        }
+        let path = session::save(arg, &context.chat_bot.session())?;
+        println!("  [Saved chat to {}]", path.display());
+        Ok(Flow::Continue)
+    }
+}
+
+struct Load;
+
+impl Command for Load {
+    fn name(&self) -> &'static str {
+        "/load"
+    }
+    fn usage(&self) -> &'static str {
+        "<name>"
+    }
+    fn help(&self) -> &'static str {
+        "Continue a saved chat."
+    }
+    fn complete(&self, arg: &str) -> Vec<String> {
+        complete_session(arg)
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        if arg.is_empty() {
+            return Err(UsageError.into());
+        }
+        load_session(context.chat_bot, arg)?;
+        Ok(Flow::Continue)
+    }
+}
+
+struct Pin;
+
+impl Command for Pin {
+    fn name(&self) -> &'static str {
+        "/pin"
+    }
+    fn help(&self) -> &'static str {
+        "Always send the last exchange, however long the chat gets."
+    }
+    fn run(&self, context: &mut Context, _arg: &str) -> Result<Flow> {
+        match context.chat_bot.pin_last_turn() {
+            0 => println!("  [Nothing to pin yet]"),
+            n => println!(
+                "  [Pinned the last {} messages so that they're always sent]",
+                n
+            ),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+struct Summarize;
+
+impl Command for Summarize {
+    fn name(&self) -> &'static str {
+        "/summarize"
+    }
+    fn help(&self) -> &'static str {
+        "Replace the older messages with a summary."
+    }
+    fn run(&self, context: &mut Context, _arg: &str) -> Result<Flow> {
+        match context.chat_bot.summarize()? {
+            0 => println!("  [Nothing to summarize yet]"),
+            n => println!("  [Summarized {} older messages]", n),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+struct Summary;
+
+impl Command for Summary {
+    fn name(&self) -> &'static str {
+        "/summary"
+    }
+    fn usage(&self) -> &'static str {
+        "[text]"
+    }
+    fn help(&self) -> &'static str {
+        "Show the summary of the older messages, or replace it."
+    }
+    fn run(&self, context: &mut Context, arg: &str) -> Result<Flow> {
+        if !arg.is_empty() {
+            context.chat_bot.set_summary(arg)?;
+            println!("  [Replaced the summary]");
+            return Ok(Flow::Continue);
+        }
+        match context.chat_bot.summary() {
+            Some(summary) => println!("  [Summary of the earlier conversation:]\n{}", summary),
+            None => println!("  [There's no summary yet. Enter `/summarize` to make one]"),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+// Describe the totals of a set of requests.
+fn describe(totals: &cost::Totals) -> String {
+    let mut text = format!(
+        "{:>9}  {:>4} requests  {:>11} prompt + {:>9} completion tokens",
+        cost::format(totals.cost),
+        totals.requests,
+        totals.prompt_tokens.separate_with_commas(),
+        totals.completion_tokens.separate_with_commas()
+    );
+    if totals.unpriced > 0 {
+        text.push_str(&format!(" ({} unpriced)", totals.unpriced));
+    }
+    text
+}
+
+struct Cost;
+
+impl Command for Cost {
+    fn name(&self) -> &'static str {
+        "/cost"
+    }
+    fn help(&self) -> &'static str {
+        "Show the cost of this session, and of all requests by day and by model."
+    }
+    fn run(&self, context: &mut Context, _arg: &str) -> Result<Flow> {
+        println!("  [This session:]");
+        println!("  {}", describe(&context.chat_bot.costs()));
+        let Some(ledger) = context.chat_bot.ledger() else {
+            return Ok(Flow::Continue);
+        };
+        let entries = cost::ledger(ledger)?;
+        if entries.is_empty() {
+            return Ok(Flow::Continue);
+        }
+        println!("  [By day:]");
+        for (day, totals) in cost::by_day(&entries) {
+            println!("  {}  {}", day, describe(&totals));
+        }
+        println!("  [By model:]");
+        let models = cost::by_model(&entries);
+        let width = models.keys().map(String::len).max().unwrap_or(0);
+        for (model, totals) in &models {
+            println!("  {:width$}  {}", model, describe(totals));
+        }
+        Ok(Flow::Continue)
+    }
+}
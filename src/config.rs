@@ -0,0 +1,475 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::persona;
+use chatgpt_api_cli::budget;
+use chatgpt_api_cli::client;
+use chatgpt_api_cli::context;
+use chatgpt_api_cli::cost;
+use chatgpt_api_cli::provider::{self, Provider};
+use chatgpt_api_cli::retry;
+use chatgpt_api_cli::tools;
+use chatgpt_api_cli::{anthropic, azure, openai};
+
+// Configuration is layered. Each layer overrides the settings it gives in the layers before it:
+// 1. The user's config file, `chatgpt_api_cli/config.toml` in the XDG config directory (e.g.
+//    `~/.config/chatgpt_api_cli/config.toml`).
+// 2. The project's config file, `.chatgpt_api_cli.toml` in the current directory. A cloned
+//    repository could use it to send the API key or the user's files elsewhere, so it can't set
+//    the settings in `PROJECT_IGNORED` below. They're ignored with a warning.
+// 3. The profile selected with `--profile`, from either file.
+// 4. The command-line arguments.
+//
+// Example config file:
+//
+//     model = "gpt-4"
+//     temperature = 0.7
+//
+//     context_policy = "last-tokens:4000"
+//
+//     [prices]
+//     "gpt-4o" = { input = 2.50, output = 10.00 }
+//
+//     [limits]
+//     request_tokens = { soft = 20000, hard = 100000 }
+//     day = { hard = 5.00 }
+//
+//     [profiles.terse]
+//     system_prompt = "Answer in one or two sentences."
+//     max_tokens = 200
+//
+//     [profiles.code-review]
+//     temperature = 0.2
+//     persona = "rust-reviewer"
+//     tools = true
+//     tool_output_tokens = 4000
+//
+//     [profiles.local]
+//     base_url = "http://localhost:11434/v1"
+//     model = "llama3.1"
+//
+//     [profiles.azure]
+//     provider = "azure"
+//     base_url = "https://my-resource.openai.azure.com"
+//     azure_deployment = "gpt-4o"
+//
+//     [profiles.claude]
+//     provider = "anthropic"
+//     model = "claude-sonnet-4-5"
+
+const USER_CONFIG_FILE: &str = "config.toml";
+const PROJECT_CONFIG_FILE: &str = ".chatgpt_api_cli.toml";
+
+// The file that contains the OpenAI API key, unless configured otherwise. It's read if the
+// provider's environment variable isn't set. See `provider::Kind::api_key_var`.
+const DEFAULT_API_KEY_FILE: &str = "open_ai_auth_key.txt";
+
+// The settings the project's config file can't set: those choosing where requests and the API key
+// go, enabling the tools, and limiting spending.
+const PROJECT_IGNORED: &str = "provider, base_url, api_key_env, api_key_file, tools, tools_dir, \
+prices and limits";
+
+// Settings holds one layer of configuration. Unset fields leave the value from the layers below in
+// place.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    // The API the chat is sent to: `openai` (the default, also for compatible servers), `azure`
+    // or `anthropic`. See `provider.rs`.
+    pub provider: Option<provider::Kind>,
+    pub model: Option<String>,
+    // The base URL of the API, by default the provider's. For Azure, the resource's endpoint.
+    pub base_url: Option<String>,
+    // The name of the Azure deployment, by default the model's name.
+    pub azure_deployment: Option<String>,
+    // The Azure OpenAI API version to request.
+    pub azure_api_version: Option<String>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub stop: Option<Vec<String>>,
+    pub presence_penalty: Option<f64>,
+    pub frequency_penalty: Option<f64>,
+    // TOML keys are always strings, so the token IDs are parsed when the options are built.
+    pub logit_bias: Option<HashMap<String, f64>>,
+    pub user: Option<String>,
+    // The number of alternative replies to generate for each prompt, to choose between.
+    pub n: Option<u32>,
+    // Whether to print replies as they're generated.
+    pub stream: Option<bool>,
+    // Whether to print replies as the model wrote them instead of rendering their Markdown.
+    pub raw: Option<bool>,
+    // The instruction sent to the model as a system message at the start of each chat.
+    pub system_prompt: Option<String>,
+    // The persona whose file holds the system prompt, if `system_prompt` isn't given. See
+    // `persona.rs`.
+    pub persona: Option<String>,
+    // The environment variable holding the API key.
+    pub api_key_env: Option<String>,
+    // The file holding the API key, read if the environment variable isn't set.
+    pub api_key_file: Option<PathBuf>,
+    // The number of times to retry a request that failed with a rate limit, server error or
+    // network timeout.
+    pub max_retries: Option<u32>,
+    // The delay in seconds before the first retry. It doubles with each retry after that.
+    pub retry_base_delay: Option<f64>,
+    // The longest delay in seconds between retries. A request the server asks to wait longer for
+    // fails instead.
+    pub retry_max_delay: Option<f64>,
+    // Which messages to send when the chat is too long for the model: `full`, `drop-oldest`,
+    // `last-tokens:<N>` or `summarize[:<N>]`.
+    pub context_policy: Option<context::Policy>,
+    // The model's context window in tokens, for models the program doesn't know.
+    pub context_window: Option<usize>,
+    // The prices of models by model name prefix, overriding the built-in prices.
+    pub prices: Option<HashMap<String, cost::Price>>,
+    // The limits on the size of requests and on spending. See `budget.rs`.
+    pub limits: Option<budget::Limits>,
+    // Whether to offer the model the built-in tools. See `tools.rs`.
+    pub tools: Option<bool>,
+    // The directory the built-in tools are confined to, by default the current directory.
+    pub tools_dir: Option<PathBuf>,
+    // The most tokens of output returned to the model from each tool call.
+    pub tool_output_tokens: Option<usize>,
+}
+
+// Override each field of `$base` with the field of `$layer` if it's set.
+macro_rules! merge_fields {
+    ($base:expr, $layer:expr, $($field:ident),*) => {
+        $(if $layer.$field.is_some() {
+            $base.$field = $layer.$field;
+        })*
+    };
+}
+
+// Unset each field of `$settings` that's set, and return the names of those fields.
+macro_rules! take_fields {
+    ($settings:expr, $($field:ident),*) => {{
+        let mut taken = vec![];
+        $(if $settings.$field.take().is_some() {
+            taken.push(stringify!($field));
+        })*
+        taken
+    }};
+}
+
+impl Settings {
+    // Unset the settings that the project's config file can't set. Return the names of those that
+    // were set.
+    fn take_project_ignored(&mut self) -> Vec<&'static str> {
+        take_fields!(
+            self,
+            provider,
+            base_url,
+            api_key_env,
+            api_key_file,
+            tools,
+            tools_dir,
+            prices,
+            limits
+        )
+    }
+
+    // Apply the settings of the given layer on top of these.
+    pub fn merge(&mut self, layer: Settings) {
+        // A system prompt and a persona are alternatives, so a layer giving either replaces both.
+        if layer.system_prompt.is_some() || layer.persona.is_some() {
+            self.system_prompt = None;
+            self.persona = None;
+        }
+        merge_fields!(
+            self,
+            layer,
+            provider,
+            model,
+            base_url,
+            azure_deployment,
+            azure_api_version,
+            temperature,
+            top_p,
+            max_tokens,
+            stop,
+            presence_penalty,
+            frequency_penalty,
+            logit_bias,
+            user,
+            n,
+            stream,
+            raw,
+            system_prompt,
+            persona,
+            api_key_env,
+            api_key_file,
+            max_retries,
+            retry_base_delay,
+            retry_max_delay,
+            context_policy,
+            context_window,
+            prices,
+            limits,
+            tools,
+            tools_dir,
+            tool_output_tokens
+        );
+    }
+
+    // The client options selected by the settings, with defaults for any left unset.
+    pub fn options(&self) -> Result<client::Options> {
+        let defaults = client::Options::default();
+        let logit_bias = match &self.logit_bias {
+            Some(bias) => Some(
+                bias.iter()
+                    .map(|(token, bias)| {
+                        let token = token
+                            .parse()
+                            .with_context(|| format!("invalid logit bias token: {}", token))?;
+                        Ok((token, *bias))
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            None => None,
+        };
+        let kind = self.provider.unwrap_or_default();
+        let base_url = self
+            .base_url
+            .clone()
+            .or(kind.default_base_url().map(str::to_string))
+            .ok_or_else(|| {
+                anyhow!(
+                    "the {} provider needs `base_url`, such as https://<resource>.openai.azure.com",
+                    kind
+                )
+            })?;
+        Ok(client::Options {
+            model: self.model.clone().unwrap_or(defaults.model),
+            base_url,
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            stop: self.stop.clone(),
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+            logit_bias,
+            user: self.user.clone(),
+            n: self.n,
+        })
+    }
+
+    // The retry policy selected by the settings, with defaults for any left unset.
+    pub fn retry_policy(&self) -> Result<retry::Policy> {
+        let defaults = retry::Policy::default();
+        let secs = |name, value: Option<f64>, default| match value {
+            Some(secs) => Duration::try_from_secs_f64(secs)
+                .with_context(|| format!("invalid {}: {}", name, secs)),
+            None => Ok(default),
+        };
+        Ok(retry::Policy {
+            max_retries: self.max_retries.unwrap_or(defaults.max_retries),
+            base_delay: secs(
+                "retry_base_delay",
+                self.retry_base_delay,
+                defaults.base_delay,
+            )?,
+            max_delay: secs("retry_max_delay", self.retry_max_delay, defaults.max_delay)?,
+        })
+    }
+
+    // The built-in tools, if they're enabled, confined to the configured directory.
+    pub fn tools(&self) -> Result<Vec<Box<dyn tools::Tool>>> {
+        if !self.tools.unwrap_or(false) {
+            return Ok(vec![]);
+        }
+        let workspace = tools::Workspace::new(
+            self.tools_dir.as_deref().unwrap_or(Path::new(".")),
+            self.tool_output_tokens
+                .unwrap_or(tools::DEFAULT_OUTPUT_TOKENS),
+        )?;
+        Ok(tools::builtin(workspace))
+    }
+
+    // The system prompt given directly or by the persona, if any.
+    pub fn system_prompt(&self) -> Result<Option<String>> {
+        match (&self.system_prompt, &self.persona) {
+            (Some(prompt), _) => Ok(Some(prompt.clone())),
+            (None, Some(name)) => persona::load(name).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+
+    // Obtain the API key from the configured environment variable, by default the provider's. If
+    // not defined, read it from the configured file. Only OpenAI's key has a default file.
+    pub fn api_key(&self) -> Result<String> {
+        let kind = self.provider.unwrap_or_default();
+        let var = self.api_key_env.as_deref().unwrap_or(kind.api_key_var());
+        if let Ok(s) = env::var(var) {
+            return Ok(s);
+        }
+        let file = match (&self.api_key_file, kind) {
+            (Some(file), _) => file.as_path(),
+            (None, provider::Kind::OpenAi) => Path::new(DEFAULT_API_KEY_FILE),
+            (None, _) => {
+                return Err(anyhow!(
+                    "couldn't find {} API key in environment variable (${})",
+                    kind,
+                    var
+                ))
+            }
+        };
+        fs::read_to_string(file)
+            .context(format!("error reading auth token file: {}", file.display()))
+            .map(|s| s.trim().to_string())
+            .context(format!(
+                "couldn't find {} API key in environment variable (${}) or file",
+                kind, var
+            ))
+    }
+
+    // The provider selected by the settings, sending requests with the given client.
+    pub fn provider(&self, client: client::Client) -> Result<Box<dyn Provider>> {
+        Ok(match self.provider.unwrap_or_default() {
+            provider::Kind::OpenAi => {
+                // Servers compatible with OpenAI's API often don't need a key, so none is sent if
+                // the base URL isn't OpenAI's and no key is configured.
+                let optional = self.api_key_env.is_none()
+                    && self.api_key_file.is_none()
+                    && client.options().base_url != client::DEFAULT_BASE_URL;
+                let key = match self.api_key() {
+                    Ok(key) => Some(key),
+                    Err(_) if optional => None,
+                    Err(e) => return Err(e),
+                };
+                Box::new(openai::OpenAi::new(client, key))
+            }
+            provider::Kind::Azure => Box::new(azure::Azure::new(
+                client,
+                self.api_key()?,
+                self.azure_deployment.clone(),
+                self.azure_api_version.clone(),
+            )),
+            provider::Kind::Anthropic => {
+                Box::new(anthropic::Anthropic::new(client, self.api_key()?))
+            }
+        })
+    }
+}
+
+// ConfigFile is the contents of one config file: default settings plus named profiles.
+#[derive(Debug, Default)]
+struct ConfigFile {
+    defaults: Settings,
+    profiles: HashMap<String, Settings>,
+}
+
+impl ConfigFile {
+    // Read the config file at the given path. A missing file is treated as empty.
+    fn read(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e).context(format!("error reading config file: {}", path.display()))
+            }
+        };
+        Self::parse(&text).context(format!("error parsing config file: {}", path.display()))
+    }
+
+    // Parse the contents of a config file. The profiles are separated from the default settings by
+    // hand, as unknown keys can't be reported in a flattened struct.
+    fn parse(text: &str) -> Result<Self> {
+        let mut table: toml::Table = toml::from_str(text)?;
+        let profiles = match table.remove("profiles") {
+            Some(toml::Value::Table(profiles)) => profiles
+                .into_iter()
+                .map(|(name, profile)| {
+                    let settings = profile
+                        .try_into()
+                        .with_context(|| format!("invalid profile `{}`", name))?;
+                    Ok((name, settings))
+                })
+                .collect::<Result<_>>()?,
+            Some(_) => bail!("`profiles` must be a table"),
+            None => HashMap::new(),
+        };
+        Ok(Self {
+            defaults: toml::Value::Table(table).try_into()?,
+            profiles,
+        })
+    }
+
+    // Unset the settings that the project's config file can't set, in the defaults and the
+    // profiles. Return the names of those that were set, with the profiles they were set in.
+    fn take_project_ignored(&mut self) -> Vec<String> {
+        let mut ignored: Vec<_> = self
+            .defaults
+            .take_project_ignored()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let mut names: Vec<_> = self.profiles.keys().cloned().collect();
+        names.sort();
+        for name in names {
+            let profile = self.profiles.get_mut(&name).unwrap();
+            for field in profile.take_project_ignored() {
+                ignored.push(format!("profiles.{}.{}", name, field));
+            }
+        }
+        ignored
+    }
+
+    // Apply the given file on top of this one. Profiles with the same name are merged.
+    fn merge(&mut self, other: ConfigFile) {
+        self.defaults.merge(other.defaults);
+        for (name, settings) in other.profiles {
+            self.profiles.entry(name).or_default().merge(settings);
+        }
+    }
+}
+
+// The directory holding the user's configuration for this program, if the platform has one.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(chatgpt_api_cli::APP_DIR))
+}
+
+// Load the settings from the user's and the project's config files, with the named profile, if
+// any, applied on top.
+pub fn load(profile: Option<&str>) -> Result<Settings> {
+    let mut config = ConfigFile::default();
+    if let Some(dir) = config_dir() {
+        config.merge(ConfigFile::read(&dir.join(USER_CONFIG_FILE))?);
+    }
+    let mut project = ConfigFile::read(Path::new(PROJECT_CONFIG_FILE))?;
+    let ignored = project.take_project_ignored();
+    if !ignored.is_empty() {
+        eprintln!(
+            "[Ignoring {} in {}: the project's config file can't set {}]",
+            ignored.join(", "),
+            PROJECT_CONFIG_FILE,
+            PROJECT_IGNORED
+        );
+    }
+    config.merge(project);
+
+    let mut settings = config.defaults;
+    if let Some(name) = profile {
+        let layer = config.profiles.remove(name).ok_or_else(|| {
+            let mut names: Vec<_> = config.profiles.keys().map(String::as_str).collect();
+            names.sort();
+            anyhow!(
+                "unknown profile `{}` (available: {})",
+                name,
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            )
+        })?;
+        settings.merge(layer);
+    }
+    Ok(settings)
+}
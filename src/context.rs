@@ -0,0 +1,305 @@
+use anyhow::{anyhow, Error, Result};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::api;
+use crate::tokens;
+
+// The whole chat history is sent with each request, so long chats eventually exceed the model's
+// context window: the most tokens it can handle for the prompt and the reply together. The context
+// policy decides which messages are left out of the request to make it fit. The chat history
+// itself is never changed by dropping, so dropped messages are still saved and can be sent to a
+// model with a larger window. Alternatively, the oldest messages can be replaced in the history by
+// a summary of them. See `ChatBot::summarize`.
+
+// The tokens reserved for the reply when `max_tokens` isn't set.
+pub const DEFAULT_REPLY_TOKENS: usize = 1024;
+
+// The context window of models not in the table below.
+const DEFAULT_CONTEXT_WINDOW: usize = 8_192;
+
+// The context windows of the OpenAI and Anthropic models, by model name prefix. The first matching
+// prefix wins, so longer prefixes come first.
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("chatgpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-1106", 128_000),
+    ("gpt-4-0125", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo-instruct", 4_096),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("claude", 200_000),
+];
+
+// Whether the message at the index starts a turn. A turn is the user's messages, the prompt and any
+// files attached to it, followed by the replies to them.
+pub(crate) fn starts_turn(messages: &[api::Message], i: usize) -> bool {
+    messages[i].role == "user" && (i == 0 || messages[i - 1].role != "user")
+}
+
+// The context window of the named model, in tokens.
+pub fn context_window(model: &str) -> usize {
+    CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map_or(DEFAULT_CONTEXT_WINDOW, |(_, window)| *window)
+}
+
+// Policy selects which messages are sent when the history doesn't fit. System messages, pinned
+// messages and the latest turn are always sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Policy {
+    // Send the whole history, even if the API will reject it.
+    Full,
+    // Drop the oldest turns until the history fits in the context window, less the tokens reserved
+    // for the reply.
+    #[default]
+    DropOldest,
+    // Drop the oldest turns until the history fits in the given number of tokens, or the context
+    // window if that's smaller.
+    LastTokens(usize),
+    // Summarize the oldest turns when the history grows past the given number of tokens, or past
+    // the context window if not given. Turns are still dropped if the history doesn't fit after
+    // that.
+    Summarize(Option<usize>),
+}
+
+impl FromStr for Policy {
+    type Err = Error;
+
+    // Parse `full`, `drop-oldest`, `last-tokens:<N>`, `summarize` or `summarize:<N>`.
+    fn from_str(s: &str) -> Result<Self> {
+        let tokens = |n: &str| {
+            n.trim()
+                .parse()
+                .map_err(|_| anyhow!("invalid token count in context policy: {}", s))
+        };
+        match s.split_once(':') {
+            None if s == "full" => Ok(Policy::Full),
+            None if s == "drop-oldest" => Ok(Policy::DropOldest),
+            None if s == "summarize" => Ok(Policy::Summarize(None)),
+            Some(("last-tokens", n)) => Ok(Policy::LastTokens(tokens(n)?)),
+            Some(("summarize", n)) => Ok(Policy::Summarize(Some(tokens(n)?))),
+            _ => Err(anyhow!(
+                "invalid context policy `{}`: expected `full`, `drop-oldest`, `last-tokens:<N>` \
+                 or `summarize[:<N>]`",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Policy {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Policy::Full => write!(f, "full"),
+            Policy::DropOldest => write!(f, "drop-oldest"),
+            Policy::LastTokens(n) => write!(f, "last-tokens:{}", n),
+            Policy::Summarize(None) => write!(f, "summarize"),
+            Policy::Summarize(Some(n)) => write!(f, "summarize:{}", n),
+        }
+    }
+}
+
+// Fitted is the part of the chat history chosen to be sent.
+pub struct Fitted {
+    pub messages: Vec<api::Message>,
+    // The number of messages left out.
+    pub dropped: usize,
+    // The estimated number of prompt tokens the messages will use.
+    pub tokens: usize,
+}
+
+// Choose the messages to send so that their prompt tokens fit in the budget, following the policy.
+// Messages are dropped a turn at a time, oldest first: a user message together with the replies to
+// it. System messages, the messages at the `pinned` indices and the latest turn are kept, even if
+// the result still doesn't fit. The latest turn is kept whole so that the results of tool calls
+// are never sent without the calls.
+pub fn fit(
+    messages: &[api::Message],
+    pinned: &BTreeSet<usize>,
+    policy: Policy,
+    window_budget: usize,
+    counter: &tokens::Counter,
+) -> Fitted {
+    let budget = match policy {
+        Policy::Full => usize::MAX,
+        Policy::DropOldest | Policy::Summarize(_) => window_budget,
+        Policy::LastTokens(n) => n.min(window_budget),
+    };
+
+    let sizes: Vec<usize> = messages.iter().map(|m| counter.message(m)).collect();
+    let mut tokens = counter.messages(&[]) + sizes.iter().sum::<usize>();
+    let mut keep = vec![true; messages.len()];
+
+    // The start of the latest turn, or the latest message if there's no user message.
+    let latest = (0..messages.len())
+        .rev()
+        .find(|&i| starts_turn(messages, i))
+        .unwrap_or(messages.len().saturating_sub(1));
+    let droppable = |i: usize| i < latest && messages[i].role != "system" && !pinned.contains(&i);
+
+    let mut i = 0;
+    while tokens > budget && i < latest {
+        // Drop the turn starting at i: the message and the messages that follow it up to the next
+        // turn.
+        let mut end = i + 1;
+        while end < latest && !starts_turn(messages, end) {
+            end += 1;
+        }
+        for j in i..end {
+            if droppable(j) {
+                keep[j] = false;
+                tokens -= sizes[j];
+            }
+        }
+        i = end;
+    }
+
+    Fitted {
+        messages: messages
+            .iter()
+            .zip(&keep)
+            .filter(|(_, k)| **k)
+            .map(|(m, _)| m.clone())
+            .collect(),
+        dropped: keep.iter().filter(|k| !**k).count(),
+        tokens,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat() -> Vec<api::Message> {
+        vec![
+            api::Message::new("system", "Be brief."),
+            api::Message::new("user", "What is Rust?"),
+            api::Message::new("assistant", "A systems programming language."),
+            api::Message::new("user", "Who made it?"),
+            api::Message::new("assistant", "Graydon Hoare, at Mozilla."),
+            api::Message::new("user", "When?"),
+        ]
+    }
+
+    fn texts(messages: &[api::Message]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|m| m.text().unwrap_or_default().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn turns_start_at_the_first_of_the_users_messages() {
+        let messages = vec![
+            api::Message::new("system", "Be brief."),
+            api::Message::new("user", "Explain this file."),
+            api::Message::new("user", "fn main() {}"),
+            api::Message::new("assistant", "It does nothing."),
+            api::Message::new("user", "Thanks."),
+        ];
+        let starts: Vec<bool> = (0..messages.len())
+            .map(|i| starts_turn(&messages, i))
+            .collect();
+        assert_eq!(starts, [false, true, false, false, true]);
+    }
+
+    #[test]
+    fn everything_is_sent_when_it_fits() {
+        let counter = tokens::Counter::for_model("gpt-4o");
+        let messages = chat();
+        let fitted = fit(
+            &messages,
+            &BTreeSet::new(),
+            Policy::DropOldest,
+            10_000,
+            &counter,
+        );
+        assert_eq!(fitted.dropped, 0);
+        assert_eq!(texts(&fitted.messages), texts(&messages));
+        assert_eq!(fitted.tokens, counter.messages(&messages));
+    }
+
+    #[test]
+    fn the_oldest_turns_are_dropped_whole() {
+        let counter = tokens::Counter::for_model("gpt-4o");
+        let messages = chat();
+        // Just too small for the whole chat: dropping the first question alone would fit, but its
+        // reply goes with it.
+        let budget = counter.messages(&messages) - 1;
+        let fitted = fit(
+            &messages,
+            &BTreeSet::new(),
+            Policy::DropOldest,
+            budget,
+            &counter,
+        );
+        assert_eq!(fitted.dropped, 2);
+        assert_eq!(
+            texts(&fitted.messages),
+            [
+                "Be brief.",
+                "Who made it?",
+                "Graydon Hoare, at Mozilla.",
+                "When?"
+            ]
+        );
+        assert_eq!(fitted.tokens, counter.messages(&fitted.messages));
+    }
+
+    #[test]
+    fn system_pinned_and_latest_messages_are_kept() {
+        let counter = tokens::Counter::for_model("gpt-4o");
+        let messages = chat();
+        let pinned = BTreeSet::from([1]);
+        let fitted = fit(&messages, &pinned, Policy::DropOldest, 0, &counter);
+        assert_eq!(fitted.dropped, 3);
+        assert_eq!(
+            texts(&fitted.messages),
+            ["Be brief.", "What is Rust?", "When?"]
+        );
+    }
+
+    #[test]
+    fn the_full_policy_drops_nothing() {
+        let counter = tokens::Counter::for_model("gpt-4o");
+        let messages = chat();
+        let fitted = fit(&messages, &BTreeSet::new(), Policy::Full, 0, &counter);
+        assert_eq!(fitted.dropped, 0);
+        assert_eq!(fitted.messages.len(), messages.len());
+    }
+
+    #[test]
+    fn last_tokens_is_capped_by_the_window() {
+        let counter = tokens::Counter::for_model("gpt-4o");
+        let messages = chat();
+        let budget = counter.messages(&messages) - 1;
+        let fitted = fit(
+            &messages,
+            &BTreeSet::new(),
+            Policy::LastTokens(10_000),
+            budget,
+            &counter,
+        );
+        assert_eq!(fitted.dropped, 2);
+    }
+}
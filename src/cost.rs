@@ -0,0 +1,220 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, NaiveDate};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::api;
+
+// The cost of each request is worked out from the tokens the API reports for the prompt and the
+// completion, which are priced differently. If the chatbot is given a ledger, each request is
+// recorded in it, so that spending can be reported across runs. The ledger is a JSON Lines file;
+// the CLI keeps it as `ledger.jsonl` in the `chatgpt_api_cli` directory of the user's data
+// directory (e.g. `~/.local/share/chatgpt_api_cli/ledger.jsonl`).
+const LEDGER_FILE: &str = "ledger.jsonl";
+
+// Price is what a model charges, in US dollars per million tokens.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Price {
+    // The price of the prompt's tokens.
+    pub input: f64,
+    // The price of the completion's tokens.
+    pub output: f64,
+}
+
+impl Price {
+    const fn new(input: f64, output: f64) -> Self {
+        Self { input, output }
+    }
+
+    // The cost in US dollars of the given prompt and completion tokens.
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.input + completion_tokens as f64 * self.output) / 1_000_000.0
+    }
+}
+
+// The prices of the OpenAI and Anthropic models, by model name prefix. The first matching prefix
+// wins, so longer prefixes come first. Prices change, so they can be overridden in the config
+// files.
+const PRICES: &[(&str, Price)] = &[
+    ("gpt-4.1-nano", Price::new(0.10, 0.40)),
+    ("gpt-4.1-mini", Price::new(0.40, 1.60)),
+    ("gpt-4.1", Price::new(2.00, 8.00)),
+    ("gpt-4o-mini", Price::new(0.15, 0.60)),
+    ("gpt-4o", Price::new(2.50, 10.00)),
+    ("chatgpt-4o", Price::new(5.00, 15.00)),
+    ("gpt-4-turbo", Price::new(10.00, 30.00)),
+    ("gpt-4-1106", Price::new(10.00, 30.00)),
+    ("gpt-4-0125", Price::new(10.00, 30.00)),
+    ("gpt-4-32k", Price::new(60.00, 120.00)),
+    ("gpt-4", Price::new(30.00, 60.00)),
+    ("gpt-3.5-turbo-instruct", Price::new(1.50, 2.00)),
+    ("gpt-3.5-turbo", Price::new(0.50, 1.50)),
+    ("o1-mini", Price::new(1.10, 4.40)),
+    ("o1", Price::new(15.00, 60.00)),
+    ("o3-mini", Price::new(1.10, 4.40)),
+    ("o3", Price::new(2.00, 8.00)),
+    ("o4-mini", Price::new(1.10, 4.40)),
+    ("claude-opus-4-5", Price::new(5.00, 25.00)),
+    ("claude-opus-4", Price::new(15.00, 75.00)),
+    ("claude-sonnet-4", Price::new(3.00, 15.00)),
+    ("claude-haiku-4-5", Price::new(1.00, 5.00)),
+    ("claude-3-7-sonnet", Price::new(3.00, 15.00)),
+    ("claude-3-5-sonnet", Price::new(3.00, 15.00)),
+    ("claude-3-5-haiku", Price::new(0.80, 4.00)),
+    ("claude-3-opus", Price::new(15.00, 75.00)),
+    ("claude-3-haiku", Price::new(0.25, 1.25)),
+];
+
+// Pricing finds the price of a model, preferring the prices given in the config files.
+#[derive(Debug, Default, Clone)]
+pub struct Pricing {
+    // The configured prices by model name prefix, longest first.
+    overrides: Vec<(String, Price)>,
+}
+
+impl Pricing {
+    pub fn new(overrides: HashMap<String, Price>) -> Self {
+        let mut overrides: Vec<_> = overrides.into_iter().collect();
+        overrides.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+        Self { overrides }
+    }
+
+    // The price of the named model, if it's known.
+    pub fn price(&self, model: &str) -> Option<Price> {
+        self.overrides
+            .iter()
+            .map(|(prefix, price)| (prefix.as_str(), *price))
+            .chain(PRICES.iter().copied())
+            .find(|(prefix, _)| model.starts_with(prefix))
+            .map(|(_, price)| price)
+    }
+
+    // The cost in US dollars of the tokens used, if the model's price is known.
+    pub fn cost(&self, model: &str, usage: &api::Usage) -> Option<f64> {
+        let price = self.price(model)?;
+        Some(price.cost(usage.prompt_tokens as u64, usage.completion_tokens as u64))
+    }
+}
+
+// Format a cost in US dollars.
+pub fn format(cost: f64) -> String {
+    format!("${:.4}", cost)
+}
+
+// Entry is a request recorded in the ledger.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: DateTime<Local>,
+    pub model: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    // The cost in US dollars, if the model's price was known.
+    pub cost: Option<f64>,
+}
+
+impl Entry {
+    // An entry for a request made now.
+    pub fn new(model: &str, usage: &api::Usage, pricing: &Pricing) -> Self {
+        Self {
+            time: Local::now(),
+            model: model.to_string(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            cost: pricing.cost(model, usage),
+        }
+    }
+}
+
+// Totals sums the requests in a set of entries.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Totals {
+    pub requests: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    // The cost in US dollars of the requests whose price was known.
+    pub cost: f64,
+    // The number of requests whose price wasn't known, and so aren't in `cost`.
+    pub unpriced: usize,
+}
+
+impl Totals {
+    pub fn add(&mut self, entry: &Entry) {
+        self.requests += 1;
+        self.prompt_tokens += entry.prompt_tokens as u64;
+        self.completion_tokens += entry.completion_tokens as u64;
+        match entry.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced += 1,
+        }
+    }
+}
+
+// The file holding the CLI's ledger.
+pub fn default_ledger() -> Result<PathBuf> {
+    let dir = dirs::data_dir().ok_or(anyhow!("couldn't find the user's data directory"))?;
+    Ok(dir.join(crate::APP_DIR).join(LEDGER_FILE))
+}
+
+// Add the entry to the ledger in the file.
+pub(crate) fn record(path: &Path, entry: &Entry) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .context(format!("error creating data directory: {}", dir.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("error opening ledger file: {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)
+        .context(format!("error writing ledger file: {}", path.display()))
+}
+
+// The entries in the ledger in the file, oldest first.
+pub fn ledger(path: &Path) -> Result<Vec<Entry>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).context(format!("error reading ledger file: {}", path.display())),
+    };
+    // A line spoiled by, say, an interrupted write shouldn't hide the rest of the spending.
+    Ok(text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!(
+                    "skipping line {} of ledger file {}: {}",
+                    i + 1,
+                    path.display(),
+                    e
+                );
+                None
+            }
+        })
+        .collect())
+}
+
+// The totals of the entries by local date.
+pub fn by_day(entries: &[Entry]) -> BTreeMap<NaiveDate, Totals> {
+    let mut days: BTreeMap<NaiveDate, Totals> = BTreeMap::new();
+    for entry in entries {
+        days.entry(entry.time.date_naive()).or_default().add(entry);
+    }
+    days
+}
+
+// The totals of the entries by model.
+pub fn by_model(entries: &[Entry]) -> BTreeMap<String, Totals> {
+    let mut models: BTreeMap<String, Totals> = BTreeMap::new();
+    for entry in entries {
+        models.entry(entry.model.clone()).or_default().add(entry);
+    }
+    models
+}
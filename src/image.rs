@@ -0,0 +1,111 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::fs;
+use std::path::Path;
+
+// Images are sent to vision-capable models as parts of a user message's content, each embedded as
+// a base64 `data:` URL. PNG and JPEG files are supported. See `attach.rs`.
+//
+// The tokens an image uses depend on its size: it's scaled to fit within 2048 by 2048 pixels and
+// then so that its shorter side is at most 768 pixels, and each 512-pixel tile it covers costs 170
+// tokens on top of a base of 85. Images sent at low detail cost just the base.
+// https://platform.openai.com/docs/guides/vision
+
+// The largest image file the API accepts.
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+const BASE_TOKENS: usize = 85;
+const TILE_TOKENS: usize = 170;
+const TILE_SIZE: u32 = 512;
+
+// The tokens counted for an image whose size isn't known: that of a 1024 by 1024 image.
+const DEFAULT_TOKENS: usize = BASE_TOKENS + 4 * TILE_TOKENS;
+
+// The number of base64 characters decoded to find an image's size. JPEG files may have metadata
+// before the frame header giving the size.
+const SIZE_PREFIX_CHARS: usize = 128 * 1024;
+
+// The media type of the image file's contents, if it's a PNG or JPEG image.
+pub fn media_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else {
+        None
+    }
+}
+
+// Read the image file and return it as a `data:` URL.
+pub fn data_url(path: &Path) -> Result<String> {
+    let bytes = fs::read(path).context(format!("error reading {}", path.display()))?;
+    let Some(media_type) = media_type(&bytes) else {
+        bail!("{} isn't a PNG or JPEG image", path.display());
+    };
+    if bytes.len() > MAX_IMAGE_BYTES {
+        bail!(
+            "{} is larger than the API's limit of {} MB",
+            path.display(),
+            MAX_IMAGE_BYTES / 1024 / 1024
+        );
+    }
+    Ok(format!(
+        "data:{};base64,{}",
+        media_type,
+        STANDARD.encode(&bytes)
+    ))
+}
+
+// The estimated tokens of an image sent by URL at the given detail.
+pub fn tokens(url: &str, detail: Option<&str>) -> usize {
+    if detail == Some("low") {
+        return BASE_TOKENS;
+    }
+    let size = url
+        .strip_prefix("data:")
+        .and_then(|url| url.split_once(";base64,"))
+        .and_then(|(_, data)| {
+            // Data that isn't base64 may not have a character boundary there, but won't decode.
+            let prefix = data.get(..SIZE_PREFIX_CHARS).unwrap_or(data);
+            STANDARD.decode(prefix).ok()
+        })
+        .and_then(|bytes| dimensions(&bytes));
+    let Some((width, height)) = size else {
+        return DEFAULT_TOKENS;
+    };
+
+    let (mut width, mut height) = (width as f64, height as f64);
+    let fit = (2048.0 / width.max(height)).min(1.0);
+    (width, height) = (width * fit, height * fit);
+    let shrink = (768.0 / width.min(height)).min(1.0);
+    (width, height) = (width * shrink, height * shrink);
+    let tiles = (width / TILE_SIZE as f64).ceil() * (height / TILE_SIZE as f64).ceil();
+    BASE_TOKENS + TILE_TOKENS * tiles as usize
+}
+
+// The width and height of a PNG or JPEG image, read from the start of its file.
+fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |i: usize| Some(u16::from_be_bytes(bytes.get(i..i + 2)?.try_into().ok()?) as u32);
+    let be32 = |i: usize| Some(u32::from_be_bytes(bytes.get(i..i + 4)?.try_into().ok()?));
+    match media_type(bytes)? {
+        // The IHDR chunk comes first, giving the width and height.
+        "image/png" => Some((be32(16)?, be32(20)?)),
+        // Walk the segments to the start-of-frame one, which gives the height and width.
+        _ => {
+            let mut i = 2;
+            loop {
+                if *bytes.get(i)? != 0xff {
+                    return None;
+                }
+                let marker = *bytes.get(i + 1)?;
+                let is_frame =
+                    matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc);
+                if is_frame {
+                    return Some((be16(i + 7)?, be16(i + 5)?));
+                }
+                i += 2 + be16(i + 2)? as usize;
+            }
+        }
+    }
+}
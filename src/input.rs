@@ -0,0 +1,222 @@
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Cmd, Config, Editor, KeyCode, KeyEvent, Modifiers};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+
+use crate::commands;
+
+// The user's input is read with a line editor: the arrow keys move within the line and recall
+// earlier lines, Ctrl-R searches them, and Tab completes slash commands, persona and session names,
+// and file paths. The lines entered are kept across runs in `chatgpt_api_cli/history.txt` in the
+// user's data directory (e.g. `~/.local/share/chatgpt_api_cli/history.txt`).
+//
+// Input can span lines. Alt-Enter starts a new line instead of sending the input, text between
+// `"""` delimiters is read until the closing delimiter, and pasted text is inserted whole. Longer
+// prompts can be written in the user's editor. See `compose`.
+const HISTORY_FILE: &str = "history.txt";
+const MAX_HISTORY: usize = 1000;
+
+// The delimiter that starts and ends multi-line input.
+const MULTILINE_DELIMITER: &str = "\"\"\"";
+
+// The editor used by `compose` if neither $VISUAL nor $EDITOR is set.
+const DEFAULT_EDITOR: &str = "vi";
+
+// The file holding the input history, if the platform has a data directory.
+fn history_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(chatgpt_api_cli::APP_DIR).join(HISTORY_FILE))
+}
+
+// Helper completes the line being edited.
+struct Helper {
+    commands: Arc<commands::Registry>,
+    files: FilenameCompleter,
+}
+
+// Candidates for completing the given prefix.
+fn candidates<'a>(prefix: &str, words: impl IntoIterator<Item = &'a str>) -> Vec<Pair> {
+    words
+        .into_iter()
+        .filter(|w| w.starts_with(prefix))
+        .map(|w| Pair {
+            display: w.to_string(),
+            replacement: w.to_string(),
+        })
+        .collect()
+}
+
+impl Completer for Helper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        if before.starts_with('/') {
+            match before.split_once(' ') {
+                None => return Ok((0, candidates(before, self.commands.names()))),
+                Some((name, arg)) if !arg.contains(' ') => {
+                    if let Some(command) = self.commands.find(name) {
+                        let values = command.complete(arg);
+                        if !values.is_empty() {
+                            let start = pos - arg.len();
+                            return Ok((start, candidates(arg, values.iter().map(String::as_str))));
+                        }
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        self.files.complete(line, pos, ctx)
+    }
+}
+
+impl Hinter for Helper {
+    type Hint = String;
+}
+
+impl Highlighter for Helper {}
+
+impl Validator for Helper {
+    // Input that opens with the multi-line delimiter continues until it's closed.
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input().trim();
+        let open = input.starts_with(MULTILINE_DELIMITER)
+            && (input.len() < 2 * MULTILINE_DELIMITER.len()
+                || !input.ends_with(MULTILINE_DELIMITER));
+        Ok(if open {
+            ValidationResult::Incomplete
+        } else {
+            ValidationResult::Valid(None)
+        })
+    }
+}
+
+impl rustyline::Helper for Helper {}
+
+// Input reads the user's lines with editing, history and completion.
+pub struct Input {
+    editor: Editor<Helper, FileHistory>,
+    history: Option<PathBuf>,
+}
+
+impl Input {
+    // Create an editor that completes the given commands, with the history of earlier runs.
+    pub fn new(commands: Arc<commands::Registry>) -> Result<Self> {
+        let config = Config::builder()
+            .max_history_size(MAX_HISTORY)?
+            .history_ignore_dups(true)?
+            .build();
+        let mut editor = Editor::with_config(config).context("error starting the line editor")?;
+        editor.set_helper(Some(Helper {
+            commands,
+            files: FilenameCompleter::new(),
+        }));
+        editor.bind_sequence(KeyEvent(KeyCode::Enter, Modifiers::ALT), Cmd::Newline);
+
+        let history = history_path();
+        if let Some(path) = &history {
+            match editor.load_history(path) {
+                Ok(()) => {}
+                Err(ReadlineError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                    if let Some(dir) = path.parent() {
+                        fs::create_dir_all(dir).context(format!(
+                            "error creating history directory: {}",
+                            dir.display()
+                        ))?;
+                    }
+                }
+                Err(e) => {
+                    return Err(e)
+                        .context(format!("error reading history file: {}", path.display()))
+                }
+            }
+        }
+        Ok(Self { editor, history })
+    }
+
+    // Read a line, trimmed, or the lines between multi-line delimiters. Ctrl-C abandons the input
+    // being edited and starts another. Return None at the end of the input, such as when the user
+    // enters Ctrl-D.
+    pub fn read_line(&mut self, prompt: &str) -> Result<Option<String>> {
+        let line = loop {
+            match self.editor.readline(prompt) {
+                Ok(line) => break line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return Ok(None),
+                Err(e) => return Err(e).context("error reading input"),
+            }
+        };
+        let line = line.trim();
+        if !line.is_empty() {
+            self.editor.add_history_entry(line)?;
+            // Save each line as it's entered so that the history survives a crash. Failing to
+            // save it isn't worth interrupting the chat over.
+            if let Some(path) = &self.history {
+                if let Err(e) = self.editor.append_history(path) {
+                    warn!("error writing history file {}: {}", path.display(), e);
+                }
+            }
+        }
+        Ok(Some(match line.strip_prefix(MULTILINE_DELIMITER) {
+            Some(rest) => rest
+                .strip_suffix(MULTILINE_DELIMITER)
+                .unwrap_or(rest)
+                .trim_matches(['\r', '\n'])
+                .to_string(),
+            None => line.to_string(),
+        }))
+    }
+
+    // Read the answer to a question, trimmed, without keeping it in the history. Return None if the
+    // user enters Ctrl-C or Ctrl-D instead.
+    pub fn ask(&mut self, question: &str) -> Result<Option<String>> {
+        match self.editor.readline(question) {
+            Ok(answer) => Ok(Some(answer.trim().to_string())),
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => Ok(None),
+            Err(e) => Err(e).context("error reading input"),
+        }
+    }
+}
+
+// Open the user's editor, $VISUAL or $EDITOR, on an empty temporary file, and return what the user
+// wrote in it, or None if they left it empty.
+pub fn compose() -> Result<Option<String>> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or(DEFAULT_EDITOR.to_string());
+    // The editor may be given with arguments, such as `code --wait`.
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(DEFAULT_EDITOR);
+
+    let file = tempfile::Builder::new()
+        .prefix("chatgpt_api_cli-")
+        .suffix(".md")
+        .tempfile()
+        .context("error creating temporary file")?;
+    let status = Command::new(program)
+        .args(words)
+        .arg(file.path())
+        .status()
+        .context(format!("error running editor `{}`", editor))?;
+    if !status.success() {
+        return Err(anyhow!("editor `{}` exited with {}", editor, status));
+    }
+    let text = fs::read_to_string(file.path())
+        .context(format!("error reading {}", file.path().display()))?;
+    let text = text.trim();
+    Ok((!text.is_empty()).then(|| text.to_string()))
+}
@@ -0,0 +1,48 @@
+// A library for chatting with models through OpenAI's chat completions API or any server
+// compatible with it, Azure OpenAI, or Anthropic's Messages API. The `chatgpt_api_cli` binary is
+// built on it, and other programs can use it the same way:
+//
+//     let options = Options::builder().model("gpt-4o").temperature(0.2).build();
+//     let client = Client::new(options, retry::Policy::default())?;
+//     let provider = OpenAi::new(client, Some(api_key));
+//     let system_prompt = Some("Answer briefly.".to_string());
+//     let mut chat_bot = ChatBot::new(Box::new(provider), true, system_prompt);
+//     let question = "What's the capital of France?";
+//     let reply = chat_bot.chat(question, |delta| print!("{}", delta)).await?;
+//
+// `Client` sends and retries requests, a `Provider` translates the chat history into an API's
+// requests and its replies back, and `ChatBot` holds a chat history, fitting it to the model's
+// context window, counting its cost and calling tools. Both are async, and need a tokio runtime
+// with time and IO enabled. `blocking::ChatBot` runs a chatbot from synchronous code.
+//
+// The command-line interface is built with the `cli` feature, which is on by default. Build with
+// `default-features = false` to leave out its dependencies, such as the line editor and the
+// Markdown renderer.
+
+pub mod anthropic;
+pub mod api;
+pub mod attach;
+pub mod azure;
+pub mod blocking;
+pub mod bot;
+pub mod budget;
+pub mod client;
+pub mod context;
+pub mod cost;
+pub mod image;
+pub mod openai;
+pub mod provider;
+pub mod retry;
+pub mod session;
+pub mod tokens;
+pub mod tools;
+
+pub use api::{ChatRequest, ChatResponse, Message};
+pub use bot::ChatBot;
+pub use client::{Client, Error, Options, OptionsBuilder};
+pub use openai::OpenAi;
+pub use provider::Provider;
+
+// The directory, within the user's config and data directories, that holds this library's and the
+// program's files, such as saved sessions and the ledger of costs.
+pub const APP_DIR: &str = "chatgpt_api_cli";
@@ -1,16 +1,30 @@
-use anyhow::{Context, Result};
-use std::env;
-use std::fs;
-use std::io::Write;
-use thousands::Separable;
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::io::IsTerminal;
+use std::process::ExitCode;
 
-mod api;
-mod bot;
-mod client;
+use chatgpt_api_cli::blocking;
+use chatgpt_api_cli::bot;
+use chatgpt_api_cli::client;
+use chatgpt_api_cli::cost;
+use chatgpt_api_cli::session;
+use chatgpt_api_cli::tools;
+
+mod args;
+mod commands;
+mod config;
+mod input;
+mod markdown;
+mod oneshot;
+mod persona;
+mod repl;
 
 // This project is a simple chatbot that uses the OpenAI's chat completions API and the new
 // `gpt-3.5-turbo` model to generate responses to user input. The chat history is sent to the API
 // with each request so that the API can respond within the context of the conversation.
+//
+// The chatbot itself is a library, `chatgpt_api_cli`. See `lib.rs`. This binary adds the
+// command-line interface: the arguments, config files, the line editor and the Markdown renderer.
 
 // Setup:
 // 1. Obtain an OpenAI API key from https://beta.openai.com/account/api-keys
@@ -20,96 +34,97 @@ mod client;
 //    OR:
 //    - Create a file named `open_ai_auth_key.txt` in the project directory and put the API key in
 //      it.
+//    The variable and file can be changed in the config files. See `config.rs`.
+//    To chat through Azure OpenAI, Anthropic's API or a local server compatible with OpenAI's,
+//    such as Ollama, set `provider` and `base_url` in the config files or with `--provider` and
+//    `--base-url`. See `provider.rs`.
 // Run:
 // 3. $ cargo run
 //    Or to see full API requests and responses:
 //        $ RUST_LOG=debug cargo run
+//    Or to select the model and sampling parameters (see `--help` for all of them):
+//        $ cargo run -- --model gpt-4 --temperature 0.2
+//    Or to use a profile of settings from the config files:
+//        $ cargo run -- --profile code-review
+//    Or to send a single prompt and print only the reply, for use in scripts:
+//        $ git diff | cargo run -- "Write a commit message for this diff"
 // 4. Enter text. The complete chat history is sent to the API for context and the API's response is
-//    printed.
-//    Enter `r` to resend the current chat, `c` to clear the chat history, and `q` to exit.
-
-// The environment variable that contains the OpenAI API key. Use this or the file below.
-// This environment variable will be checked first. If not defined, the code will read the file
-// below.
-const OPENAI_API_KEY_VAR: &str = "OPENAI_API_KEY";
-// The file that contains the OpenAI API key. Use this or the environment variable above.
-const OPENAI_API_KEY_FILE: &str = "open_ai_auth_key.txt";
-
-// The prompt to display initially or when the user enters an empty line.
-const PROMPT_HELP: &str = "Enter text. Enter `r` to resend the current chat, `c` to clear the chat history, and `q` to exit.";
-
-// Obtain the OpenAI API key from the environment variable OPENAI_API_KEY_ENV. If not defined, read
-// it from the file OPENAI_API_KEY_FILE.
-fn auth_token() -> Result<String> {
-    match env::var(OPENAI_API_KEY_VAR).context("OPENAI_API_KEY not set") {
-        Ok(s) => return Ok(s),
-        Err(_) => fs::read_to_string(OPENAI_API_KEY_FILE)
-            .context(format!(
-                "error reading auth token file: {}",
-                OPENAI_API_KEY_FILE
-            ))
-            .map(|s| s.trim().to_string())
-            .context(format!(
-                "couldn't find OpenAI authentication key in environment variable (${}) or file",
-                OPENAI_API_KEY_VAR
-            )),
-    }
-}
-
-// Print the API's response or the error that occurred.
-fn print_response(res: Result<(String, u32)>) {
-    match res {
-        Ok((text, tokens)) => println!(
-            "GPT [{} tokens used for this context and prompt]: {}",
-            tokens.separate_with_commas(),
-            &text
-        ),
-        Err(e) => println!("  [Error: {}]", e),
-    }
-}
+//    printed, with its Markdown rendered unless `--raw` is given. See `markdown.rs`.
+//    Input is edited with history and Tab completion, and can span lines between `"""` delimiters
+//    or with Alt-Enter. See `input.rs`.
+//    Enter `/help` to list the commands, such as `/retry` to resend the current chat, `/clear` to
+//    clear the chat history, and `/quit` to exit. See `commands.rs`.
+//    Enter `/save <name>` to save the chat and `/load <name>` to load a saved one. Or start with
+//        $ cargo run -- --resume
+//    to continue the most recently saved chat.
+//    Enter `/add <path|glob>`, or mention `@path` in a prompt, to send files with the next prompt,
+//    and `/image <path>` to send an image. See `attach.rs` and `image.rs`.
+//    Enter `/system <text>` to instruct the model with a system prompt, or `/persona <name>` to use
+//    the system prompt in a persona file. The system prompt is kept when the chat is cleared.
+//    The cost of each reply is shown and recorded, and requests that would exceed the configured
+//    limits on tokens or spending are confirmed or refused. See `cost.rs` and `budget.rs`.
+//    With `--tools`, the model can read and search the files in the current directory and, once
+//    each command is approved, run shell commands. See `tools.rs`.
+//    Press Ctrl-C while a reply is being generated to cancel the request. See `blocking.rs`.
 
 // Create a ChatGPT demo by collecting user input and sending it to the API. Print the API's
-// response and provide controls for clearing the chat history and exiting the demo.
-fn main() -> Result<()> {
+// response and provide controls for clearing the chat history and exiting the demo. If a prompt is
+// given on the command line, send just that and exit. See `oneshot.rs`.
+fn main() -> Result<ExitCode> {
     env_logger::init();
+    let args = args::Args::parse();
+    let mut settings = config::load(args.profile.as_deref())?;
+    settings.merge(args.settings());
 
-    let mut chat_bot = bot::ChatBot::new(auth_token()?);
-
-    println!("> {}", PROMPT_HELP);
-    loop {
-        print!("> ");
-        std::io::stdout().flush()?;
+    let mut client = client::Client::new(settings.options()?, settings.retry_policy()?)?;
+    let one_shot_prompt = args.one_shot_prompt();
+    client.set_retry_notifier(match one_shot_prompt {
+        Some(_) => Box::new(oneshot::print_retry),
+        None => Box::new(repl::print_retry),
+    });
+    let mut chat_bot = bot::ChatBot::new(
+        settings.provider(client)?,
+        settings.stream.unwrap_or(true),
+        settings.system_prompt()?,
+    );
+    chat_bot.set_context(
+        settings.context_policy.unwrap_or_default(),
+        settings.context_window,
+    );
+    chat_bot.set_pricing(cost::Pricing::new(
+        settings.prices.clone().unwrap_or_default(),
+    ));
+    chat_bot.set_ledger(Some(cost::default_ledger()?));
+    chat_bot.set_limits(settings.limits.unwrap_or_default());
+    let mut tools = tools::Registry::new(settings.tools()?);
+    tools.set_notifier(match one_shot_prompt {
+        Some(_) => Box::new(oneshot::print_tool_call),
+        None => Box::new(repl::print_tool_call),
+    });
+    chat_bot.set_tools(tools);
+    let mut chat_bot = blocking::ChatBot::new(chat_bot)?;
+    chat_bot.set_cancel_on_ctrl_c(true);
 
-        // Read a line of input from the user.
-        let mut input_line = String::new();
-        std::io::stdin().read_line(&mut input_line)?;
-        let input_line = input_line.trim();
+    // Render replies' Markdown unless they're wanted raw or aren't going to a terminal.
+    let render = !settings.raw.unwrap_or(false) && std::io::stdout().is_terminal();
 
-        // Handle user flow control.
-        match input_line.trim() {
-            "q" => {
-                println!("  [Exiting]");
-                break;
-            }
-            "r" => {
-                println!("  [Resending chat to {}...]", client::MODEL);
-                print_response(chat_bot.send());
-            }
-            "c" => {
-                println!("  [Clearing chat history]");
-                chat_bot.clear();
-                continue;
-            }
-            "" => {
-                println!("> {}", PROMPT_HELP);
-                continue;
-            }
-            _ => {
-                // Send the message to the API and print the response.
-                println!("  [Sending chat to {}...]", client::MODEL);
-                print_response(chat_bot.chat(input_line));
-            }
+    if let Some(name) = &args.resume {
+        let name = match name {
+            Some(name) => name.clone(),
+            None => session::latest()?.ok_or(anyhow!("there are no saved chats to resume"))?,
+        };
+        match one_shot_prompt {
+            Some(_) => chat_bot.restore(session::load(&name)?)?,
+            None => commands::load_session(&mut chat_bot, &name)?,
         }
     }
-    Ok(())
+
+    if let Some(text) = one_shot_prompt {
+        let prompt = oneshot::read_prompt(text)?;
+        oneshot::attach_references(&mut chat_bot, text)?;
+        return Ok(oneshot::run(&mut chat_bot, &prompt, render));
+    }
+
+    repl::run(&mut chat_bot, render)?;
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,296 @@
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::as_24_bit_terminal_escaped;
+use textwrap::core::display_width;
+
+// Replies are written in Markdown. The renderer formats it for the terminal as it streams in: each
+// line is rendered as soon as it's complete, so replies still appear as they're generated, a line
+// at a time. Inline styles, headings, lists and block quotes are rendered with ANSI escapes and
+// wrapped to the terminal width. Fenced code blocks are syntax-highlighted by their language and
+// not wrapped. A table is held until its last row so that its columns can be aligned.
+
+// The syntect theme used to highlight code.
+const THEME: &str = "base16-ocean.dark";
+
+// ANSI escapes for the styles used.
+const BOLD: &str = "\x1b[1m";
+const NORMAL_INTENSITY: &str = "\x1b[22m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const NO_ITALIC: &str = "\x1b[23m";
+const UNDERLINE: &str = "\x1b[4m";
+const NO_UNDERLINE: &str = "\x1b[24m";
+const STRIKETHROUGH: &str = "\x1b[9m";
+const NO_STRIKETHROUGH: &str = "\x1b[29m";
+const INLINE_CODE: &str = "\x1b[33m";
+const DEFAULT_COLOR: &str = "\x1b[39m";
+const HEADING: &str = "\x1b[1;36m";
+const RESET: &str = "\x1b[0m";
+
+// The syntax definitions bundled with syntect, loaded on first use.
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+// The theme used to highlight code, loaded on first use.
+fn theme() -> &'static Theme {
+    static THEME_SET: OnceLock<ThemeSet> = OnceLock::new();
+    &THEME_SET.get_or_init(ThemeSet::load_defaults).themes[THEME]
+}
+
+// CodeBlock is a fenced code block being received.
+struct CodeBlock {
+    // The fence that opened the block, such as "```". The block is closed by a line holding a
+    // fence at least as long, of the same character.
+    fence: String,
+    highlighter: HighlightLines<'static>,
+}
+
+// Renderer formats Markdown for the terminal as it arrives.
+pub struct Renderer {
+    // The width to wrap text to, in columns.
+    width: usize,
+    // The start of the line being received.
+    line: String,
+    // The fenced code block being received, if any.
+    code: Option<CodeBlock>,
+    // The rows of the table being received.
+    table: Vec<String>,
+}
+
+impl Renderer {
+    // Create a renderer that wraps text to the width of the terminal.
+    pub fn new() -> Self {
+        Self {
+            width: textwrap::termwidth(),
+            line: String::new(),
+            code: None,
+            table: vec![],
+        }
+    }
+
+    // Add the next fragment of the text. Return the rendering of the lines it completes.
+    pub fn push(&mut self, delta: &str) -> String {
+        self.line.push_str(delta);
+        let mut out = String::new();
+        while let Some(end) = self.line.find('\n') {
+            let line: String = self.line.drain(..=end).collect();
+            self.render_line(line.trim_end_matches(['\n', '\r']), &mut out);
+        }
+        out
+    }
+
+    // Render the rest of the text, after the last fragment has been added, and reset the renderer
+    // for the next text.
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.render_line(&line, &mut out);
+        }
+        self.render_table(&mut out);
+        self.code = None;
+        out
+    }
+
+    // Render a complete line, without its newline.
+    fn render_line(&mut self, line: &str, out: &mut String) {
+        if let Some(code) = &mut self.code {
+            let trimmed = line.trim();
+            let fence_char = code.fence.chars().next().unwrap_or('`');
+            if trimmed.len() >= code.fence.len() && trimmed.chars().all(|c| c == fence_char) {
+                self.code = None;
+                out.push_str(&format!("{}{}{}\n", DIM, line, RESET));
+                return;
+            }
+            let line = format!("{}\n", line);
+            match code.highlighter.highlight_line(&line, syntaxes()) {
+                Ok(ranges) => {
+                    out.push_str(&as_24_bit_terminal_escaped(&ranges, false));
+                    out.push_str(RESET);
+                }
+                Err(_) => out.push_str(&line),
+            }
+            return;
+        }
+
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('|') {
+            self.table.push(trimmed.to_string());
+            return;
+        }
+        self.render_table(out);
+
+        if let Some(fence) = opening_fence(trimmed) {
+            let language = trimmed[fence.len()..].split_whitespace().next();
+            let syntax = language
+                .and_then(|l| syntaxes().find_syntax_by_token(l))
+                .unwrap_or_else(|| syntaxes().find_syntax_plain_text());
+            self.code = Some(CodeBlock {
+                fence: fence.to_string(),
+                highlighter: HighlightLines::new(syntax, theme()),
+            });
+            out.push_str(&format!("{}{}{}\n", DIM, line, RESET));
+            return;
+        }
+
+        if trimmed.is_empty() {
+            out.push('\n');
+            return;
+        }
+        let indent = &line[..line.len() - trimmed.len()];
+        let (prefix, text) = render_inline(trimmed, self.width);
+        let first = format!("{}{}", indent, prefix);
+        // Continuation lines are indented to line up with the text after a list marker, and keep
+        // a block quote's bar.
+        let rest = if prefix.contains('│') {
+            first.clone()
+        } else {
+            " ".repeat(display_width(&first))
+        };
+        let options = textwrap::Options::new(self.width)
+            .initial_indent(&first)
+            .subsequent_indent(&rest);
+        for wrapped in textwrap::wrap(&text, options) {
+            out.push_str(&wrapped);
+            out.push_str(RESET);
+            out.push('\n');
+        }
+    }
+
+    // Render the table received so far, if any, with its columns aligned.
+    fn render_table(&mut self, out: &mut String) {
+        if self.table.is_empty() {
+            return;
+        }
+        let rows: Vec<Vec<String>> = self
+            .table
+            .drain(..)
+            .map(|row| {
+                let row = row.trim();
+                let row = row.strip_prefix('|').unwrap_or(row);
+                let row = row.strip_suffix('|').unwrap_or(row);
+                row.split('|').map(|cell| cell.trim().to_string()).collect()
+            })
+            .collect();
+        // The row separating the header from the body, such as `|---|:--:|`.
+        let is_separator = |row: &Vec<String>| {
+            row.iter().all(|cell| {
+                let cell = cell.trim_matches(':');
+                !cell.is_empty() && cell.chars().all(|c| c == '-')
+            })
+        };
+        // Render the cells of each row. The separator is left as `None`.
+        let rows: Vec<Option<Vec<String>>> = rows
+            .iter()
+            .map(|row| {
+                (!is_separator(row)).then(|| {
+                    row.iter()
+                        .map(|cell| {
+                            let (prefix, text) = render_inline(cell, usize::MAX);
+                            prefix + &text
+                        })
+                        .collect()
+                })
+            })
+            .collect();
+        let mut widths: Vec<usize> = vec![];
+        for row in rows.iter().flatten() {
+            for (i, cell) in row.iter().enumerate() {
+                let width = display_width(cell);
+                match widths.get_mut(i) {
+                    Some(w) => *w = (*w).max(width),
+                    None => widths.push(width),
+                }
+            }
+        }
+
+        for (i, row) in rows.iter().enumerate() {
+            let Some(row) = row else {
+                let rule: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+                out.push_str(&format!("{}{}{}\n", DIM, rule.join("┼"), RESET));
+                continue;
+            };
+            // The row before the separator is the header.
+            let header = matches!(rows.get(i + 1), Some(None));
+            let cells: Vec<String> = widths
+                .iter()
+                .enumerate()
+                .map(|(j, width)| {
+                    let cell = row.get(j).map_or("", String::as_str);
+                    let padding = " ".repeat(width - display_width(cell));
+                    if header {
+                        format!(" {}{}{}{} ", BOLD, cell, RESET, padding)
+                    } else {
+                        format!(" {}{}{} ", cell, RESET, padding)
+                    }
+                })
+                .collect();
+            out.push_str(&cells.join(&format!("{}│{}", DIM, RESET)));
+            out.push('\n');
+        }
+    }
+}
+
+// The fence that opens a fenced code block, if the line starts one.
+fn opening_fence(line: &str) -> Option<&str> {
+    let fence_char = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = line.chars().take_while(|c| *c == fence_char).count();
+    (len >= 3).then(|| &line[..len])
+}
+
+// Render a line of Markdown that's not in a code block or table. Return the prefix that marks its
+// block, such as a list bullet, and its text with inline styles. A rule is drawn across the given
+// width, up to 80 columns.
+fn render_inline(line: &str, width: usize) -> (String, String) {
+    let mut prefix = String::new();
+    let mut text = String::new();
+    let mut ordered: Option<u64> = None;
+    let mut links: Vec<String> = vec![];
+    let options = Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    for event in Parser::new_ext(line, options) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => text.push_str(HEADING),
+            Event::End(TagEnd::Heading(_)) => text.push_str(RESET),
+            Event::Start(Tag::BlockQuote(_)) => {
+                prefix.push_str(&format!("{}│{} ", DIM, RESET));
+                text.push_str(ITALIC);
+            }
+            Event::Start(Tag::List(start)) => ordered = start,
+            Event::Start(Tag::Item) => match ordered {
+                Some(n) => prefix.push_str(&format!("{}. ", n)),
+                None => prefix.push_str("• "),
+            },
+            Event::TaskListMarker(done) => text.push_str(if done { "☑ " } else { "☐ " }),
+            Event::Start(Tag::Strong) => text.push_str(BOLD),
+            Event::End(TagEnd::Strong) => text.push_str(NORMAL_INTENSITY),
+            Event::Start(Tag::Emphasis) => text.push_str(ITALIC),
+            Event::End(TagEnd::Emphasis) => text.push_str(NO_ITALIC),
+            Event::Start(Tag::Strikethrough) => text.push_str(STRIKETHROUGH),
+            Event::End(TagEnd::Strikethrough) => text.push_str(NO_STRIKETHROUGH),
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                text.push_str(UNDERLINE);
+                links.push(dest_url.to_string());
+            }
+            Event::End(TagEnd::Link) => {
+                text.push_str(NO_UNDERLINE);
+                if let Some(url) = links.pop() {
+                    text.push_str(&format!(" {}({}){}", DIM, url, NORMAL_INTENSITY));
+                }
+            }
+            Event::Code(code) => {
+                text.push_str(&format!("{}{}{}", INLINE_CODE, code, DEFAULT_COLOR))
+            }
+            Event::Rule => text.push_str(&format!("{}{}", DIM, "─".repeat(width.min(80)))),
+            Event::Text(t) | Event::Html(t) | Event::InlineHtml(t) => text.push_str(&t),
+            Event::InlineMath(t) | Event::DisplayMath(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            _ => {}
+        }
+    }
+    (prefix, text)
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use std::io::{self, IsTerminal, Read, Write};
+use std::process::ExitCode;
+
+use crate::markdown;
+use chatgpt_api_cli::api;
+use chatgpt_api_cli::attach;
+use chatgpt_api_cli::blocking;
+use chatgpt_api_cli::bot;
+use chatgpt_api_cli::budget;
+use chatgpt_api_cli::client;
+use chatgpt_api_cli::retry;
+
+// In one-shot mode a single prompt is sent and only the reply is written to stdout, so that the
+// program can be used in shell scripts, git hooks and Makefiles:
+//
+//     $ chatgpt_api_cli -p "Write a haiku about Rust"
+//     $ git diff | chatgpt_api_cli "Write a commit message for this diff"
+//
+// Input piped to stdin is appended to the prompt. If several alternative replies are asked for with
+// `--n`, they're all written, separated by `---` lines. Diagnostics, such as retry notices and
+// errors, go to stderr, and the exit code tells what went wrong.
+
+// The separator written between alternative replies, when several are asked for.
+const REPLY_SEPARATOR: &str = "\n\n---\n\n";
+
+// The exit codes for each kind of error. Usage errors exit with 2, as reported by clap.
+const EXIT_ERROR: u8 = 1;
+const EXIT_AUTH: u8 = 3;
+const EXIT_RATE_LIMIT: u8 = 4;
+const EXIT_QUOTA_EXCEEDED: u8 = 5;
+const EXIT_CONTEXT_LENGTH_EXCEEDED: u8 = 6;
+const EXIT_INVALID_REQUEST: u8 = 7;
+const EXIT_SERVER: u8 = 8;
+const EXIT_TRANSPORT: u8 = 9;
+const EXIT_OVER_BUDGET: u8 = 10;
+const EXIT_STREAM: u8 = 11;
+// The conventional exit code for a program ended by Ctrl-C (SIGINT).
+const EXIT_CANCELLED: u8 = 130;
+
+// The exit code for an error.
+fn exit_code(e: &anyhow::Error) -> u8 {
+    if e.is::<budget::Overrun>() {
+        return EXIT_OVER_BUDGET;
+    }
+    if e.is::<blocking::Cancelled>() {
+        return EXIT_CANCELLED;
+    }
+    match e.downcast_ref::<client::Error>() {
+        Some(client::Error::Auth { .. }) => EXIT_AUTH,
+        Some(client::Error::RateLimit { .. }) => EXIT_RATE_LIMIT,
+        Some(client::Error::QuotaExceeded { .. }) => EXIT_QUOTA_EXCEEDED,
+        Some(client::Error::ContextLengthExceeded { .. }) => EXIT_CONTEXT_LENGTH_EXCEEDED,
+        Some(client::Error::InvalidRequest { .. }) => EXIT_INVALID_REQUEST,
+        Some(client::Error::Server { .. }) => EXIT_SERVER,
+        Some(client::Error::Stream { .. }) => EXIT_STREAM,
+        Some(client::Error::Transport(_)) => EXIT_TRANSPORT,
+        Some(client::Error::Response(_)) | None => EXIT_ERROR,
+    }
+}
+
+// Tell the user on stderr that a failed request is about to be retried.
+pub fn print_retry(notice: &retry::Notice) {
+    eprintln!("[{}]", notice);
+}
+
+// Tell the user on stderr that the model is calling a tool.
+pub fn print_tool_call(call: &api::ToolCall) {
+    eprintln!(
+        "[Calling {} with {}]",
+        call.function.name, call.function.arguments
+    );
+}
+
+// The prompt to send: the given text, followed by the input piped to stdin, if any.
+pub fn read_prompt(text: &str) -> Result<String> {
+    let mut stdin = io::stdin();
+    if stdin.is_terminal() {
+        return Ok(text.to_string());
+    }
+    let mut input = String::new();
+    stdin
+        .read_to_string(&mut input)
+        .context("error reading stdin")?;
+    Ok(match (text.is_empty(), input.trim().is_empty()) {
+        (_, true) => text.to_string(),
+        (true, false) => input,
+        (false, false) => format!("{}\n\n{}", text, input),
+    })
+}
+
+// Write text to stdout right away, noting whether it ended a line. A closed stdout, such as a pipe
+// to `head`, isn't worth failing the request over.
+fn write_now(text: &str, at_line_start: &mut bool) {
+    if text.is_empty() {
+        return;
+    }
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(text.as_bytes());
+    let _ = stdout.flush();
+    *at_line_start = text.ends_with('\n');
+}
+
+// Write all the alternative replies to stdout, separated by rules, rendering their Markdown if
+// `render` is set. Keep the first.
+fn write_replies(render: bool, replies: &[String]) -> usize {
+    let text = replies.join(REPLY_SEPARATOR);
+    let mut at_line_start = true;
+    if render {
+        let mut renderer = markdown::Renderer::new();
+        write_now(&renderer.push(&text), &mut at_line_start);
+        write_now(&renderer.finish(), &mut at_line_start);
+    } else {
+        write_now(&text, &mut at_line_start);
+    }
+    if !at_line_start {
+        write_now("\n", &mut at_line_start);
+    }
+    0
+}
+
+// Attach the files the prompt references as `@path`, noting each on stderr.
+pub fn attach_references(chat_bot: &mut bot::ChatBot, text: &str) -> Result<()> {
+    for path in attach::references(text) {
+        for attachment in attach::read(&path)? {
+            let path = attachment.path.clone();
+            let tokens = chat_bot.attach(attachment);
+            eprintln!("[Attached {} ({} tokens)]", path, tokens);
+        }
+    }
+    Ok(())
+}
+
+// Send the prompt and write the reply to stdout, rendering its Markdown if `render` is set.
+// Return the exit code.
+pub fn run(chat_bot: &mut blocking::ChatBot, prompt: &str, render: bool) -> ExitCode {
+    chat_bot.set_choose(Box::new(move |replies| write_replies(render, replies)));
+    let mut renderer = render.then(markdown::Renderer::new);
+    let mut at_line_start = true;
+    let res = chat_bot.chat(prompt, |delta| match &mut renderer {
+        Some(renderer) => write_now(&renderer.push(delta), &mut at_line_start),
+        None => write_now(delta, &mut at_line_start),
+    });
+    if let Some(renderer) = &mut renderer {
+        write_now(&renderer.finish(), &mut at_line_start);
+    }
+    if !at_line_start {
+        write_now("\n", &mut at_line_start);
+    }
+    match res {
+        Ok(reply) => {
+            if reply.summarized > 0 {
+                eprintln!("[Summarized {} older messages]", reply.summarized);
+            }
+            if reply.dropped > 0 {
+                eprintln!(
+                    "[Left out {} older messages to fit the context window]",
+                    reply.dropped
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::api;
+use crate::client::{self, Client, Error, Result};
+use crate::provider::{BoxFuture, Kind, Provider};
+
+// OpenAI's chat completions API. Servers compatible with it, such as Ollama, vLLM and llama.cpp's
+// server, are used by setting the base URL, such as `http://localhost:11434/v1` for Ollama. Those
+// servers often don't need an API key, so none is sent if there isn't one.
+// https://platform.openai.com/docs/api-reference/chat
+
+// The path of the chat completions endpoint, relative to the base URL.
+const CHAT_COMPLETIONS_PATH: &str = "/chat/completions";
+
+// The event data that ends a streamed response.
+const SSE_DONE: &str = "[DONE]";
+
+// OpenAi sends requests to OpenAI's API or a compatible server, authenticated by bearer token.
+pub struct OpenAi {
+    client: Client,
+    api_key: Option<String>,
+}
+
+impl OpenAi {
+    pub fn new(client: Client, api_key: Option<String>) -> Self {
+        Self { client, api_key }
+    }
+
+    fn headers(&self) -> Vec<(&'static str, String)> {
+        self.api_key
+            .iter()
+            .map(|key| ("Authorization", format!("Bearer {}", key)))
+            .collect()
+    }
+}
+
+impl Provider for OpenAi {
+    fn kind(&self) -> Kind {
+        Kind::OpenAi
+    }
+
+    fn client(&self) -> &Client {
+        &self.client
+    }
+
+    fn client_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    fn send_choices<'a>(
+        &'a self,
+        messages: &'a [api::Message],
+        n: u32,
+        tools: &'a [api::Tool],
+    ) -> BoxFuture<'a, Result<(Vec<api::Message>, api::Usage)>> {
+        let url = self.client.url(CHAT_COMPLETIONS_PATH);
+        Box::pin(send_choices(
+            &self.client,
+            url,
+            self.headers(),
+            messages,
+            n,
+            tools,
+        ))
+    }
+
+    fn send_streaming<'a>(
+        &'a self,
+        messages: &'a [api::Message],
+        tools: &'a [api::Tool],
+        on_delta: &'a mut (dyn FnMut(&str) + Send),
+    ) -> BoxFuture<'a, Result<(api::Message, Option<api::Usage>)>> {
+        let url = self.client.url(CHAT_COMPLETIONS_PATH);
+        Box::pin(send_streaming(
+            &self.client,
+            url,
+            self.headers(),
+            messages,
+            tools,
+            on_delta,
+        ))
+    }
+}
+
+// Build the request for the given chat history, offering the model the given tools.
+fn request(
+    options: &client::Options,
+    messages: &[api::Message],
+    stream: bool,
+    tools: &[api::Tool],
+) -> api::ChatRequest {
+    api::ChatRequest {
+        model: options.model.clone(),
+        messages: messages.to_vec(),
+        temperature: options.temperature,
+        top_p: options.top_p,
+        stream: stream.then_some(true),
+        stream_options: stream.then_some(api::StreamOptions {
+            include_usage: true,
+        }),
+        stop: options.stop.clone(),
+        max_tokens: options.max_tokens,
+        presence_penalty: options.presence_penalty,
+        frequency_penalty: options.frequency_penalty,
+        logit_bias: options.logit_bias.clone(),
+        user: options.user.clone(),
+        tools: (!tools.is_empty()).then(|| tools.to_vec()),
+        ..Default::default()
+    }
+}
+
+// Send the chat history to the chat completions endpoint at the URL, asking for `n` alternative
+// replies. Return them in the API's order, and the tokens used for all of them.
+pub(crate) async fn send_choices(
+    client: &Client,
+    url: String,
+    headers: Vec<(&'static str, String)>,
+    messages: &[api::Message],
+    n: u32,
+    tools: &[api::Tool],
+) -> Result<(Vec<api::Message>, api::Usage)> {
+    let mut request = request(client.options(), messages, false, tools);
+    request.n = (n > 1).then_some(n);
+    let resp = client.post(&url, &headers, &request).await?;
+
+    let mut r: api::ChatResponse = client::read_json(resp).await?;
+    if r.choices.is_empty() {
+        return Err(Error::Response("no choices".to_string()));
+    }
+    r.choices.sort_by_key(|c| c.index);
+    let replies = r.choices.into_iter().map(|c| c.message).collect();
+    Ok((replies, r.usage))
+}
+
+// Send the chat history to the chat completions endpoint at the URL, asking for the reply to be
+// streamed back as server-sent events, and assemble the reply from its chunks.
+pub(crate) async fn send_streaming(
+    client: &Client,
+    url: String,
+    headers: Vec<(&'static str, String)>,
+    messages: &[api::Message],
+    tools: &[api::Tool],
+    on_delta: &mut (dyn FnMut(&str) + Send),
+) -> Result<(api::Message, Option<api::Usage>)> {
+    let request = request(client.options(), messages, true, tools);
+    let resp = client.post(&url, &headers, &request).await?;
+
+    let mut reply = StreamedReply::default();
+    client::read_events(resp, |data| reply.push(data, on_delta)).await?;
+    Ok(reply.finish())
+}
+
+// StreamedReply assembles a reply from the chunks of a streamed response.
+#[derive(Default)]
+struct StreamedReply {
+    role: Option<String>,
+    content: String,
+    tool_calls: Vec<api::ToolCall>,
+    usage: Option<api::Usage>,
+}
+
+impl StreamedReply {
+    // Add the chunk carried by an event's data, calling `on_delta` with any text it adds. Return
+    // whether the event ends the stream.
+    fn push(&mut self, data: &str, on_delta: &mut (dyn FnMut(&str) + Send)) -> Result<bool> {
+        if data == SSE_DONE {
+            return Ok(true);
+        }
+
+        let chunk: api::ChatCompletionChunk = match serde_json::from_str(data) {
+            Ok(chunk) => chunk,
+            // The API reports errors that happen after the stream has started as an event.
+            Err(e) => match serde_json::from_str::<api::ErrorResponse>(data) {
+                Ok(_) => return Err(Error::from_stream_event(data)),
+                Err(_) => {
+                    return Err(Error::Response(format!(
+                        "error decoding response chunk: {}",
+                        e
+                    )))
+                }
+            },
+        };
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage;
+        }
+        // Only the first choice is requested, so ignore any others.
+        if let Some(choice) = chunk.choices.into_iter().find(|c| c.index == 0) {
+            if choice.delta.role.is_some() {
+                self.role = choice.delta.role;
+            }
+            if let Some(fragment) = choice.delta.content {
+                on_delta(&fragment);
+                self.content.push_str(&fragment);
+            }
+            for delta in choice.delta.tool_calls.into_iter().flatten() {
+                let i = delta.index as usize;
+                if self.tool_calls.len() <= i {
+                    self.tool_calls.resize_with(i + 1, Default::default);
+                }
+                let call = &mut self.tool_calls[i];
+                if let Some(id) = delta.id {
+                    call.id = id;
+                }
+                if let Some(kind) = delta.kind {
+                    call.kind = kind;
+                }
+                if let Some(function) = delta.function {
+                    call.function
+                        .name
+                        .push_str(&function.name.unwrap_or_default());
+                    call.function
+                        .arguments
+                        .push_str(&function.arguments.unwrap_or_default());
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    // The assembled reply message and the tokens used, if the API reported them.
+    fn finish(self) -> (api::Message, Option<api::Usage>) {
+        let tool_calls = self.tool_calls;
+        let content = self.content;
+        // A reply that only calls tools has no text.
+        let reply = api::Message {
+            role: self.role.unwrap_or_else(|| "assistant".to_string()),
+            content: (tool_calls.is_empty() || !content.is_empty()).then_some(content.into()),
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            tool_call_id: None,
+        };
+        (reply, self.usage)
+    }
+}
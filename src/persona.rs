@@ -0,0 +1,56 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config;
+
+// A persona is a named system prompt kept in a Markdown file, `<name>.md`, in the `personas`
+// directory of the user's config directory (e.g. `~/.config/chatgpt_api_cli/personas`). The whole
+// file becomes the system prompt.
+const PERSONAS_DIR: &str = "personas";
+const PERSONA_EXTENSION: &str = "md";
+
+// The directory holding the persona files.
+fn personas_dir() -> Result<PathBuf> {
+    config::config_dir()
+        .map(|dir| dir.join(PERSONAS_DIR))
+        .ok_or(anyhow!("couldn't find the user's config directory"))
+}
+
+// The names of the available personas, sorted.
+pub fn list() -> Result<Vec<String>> {
+    let dir = personas_dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => {
+            return Err(e).context(format!(
+                "error reading personas directory: {}",
+                dir.display()
+            ))
+        }
+    };
+    let mut names = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(PERSONA_EXTENSION) {
+            continue;
+        }
+        if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+// Load the system prompt of the named persona.
+pub fn load(name: &str) -> Result<String> {
+    if name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(anyhow!("invalid persona name `{}`", name));
+    }
+    let path = personas_dir()?.join(format!("{}.{}", name, PERSONA_EXTENSION));
+    let prompt = fs::read_to_string(&path)
+        .context(format!("error reading persona file: {}", path.display()))?;
+    Ok(prompt.trim().to_string())
+}
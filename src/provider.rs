@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+
+use crate::anthropic;
+use crate::api;
+use crate::client::{self, Client, Result};
+
+// A provider is an API that the chatbot can send its chat history to. The history is kept in the
+// OpenAI chat format of `api.rs`, and each provider translates it into its own requests and
+// translates the replies back:
+// - `openai`: OpenAI's chat completions API, and servers compatible with it, such as Ollama, vLLM
+//   and llama.cpp's server. See `openai.rs`.
+// - `azure`: Azure OpenAI, which serves the same API from per-deployment URLs. See `azure.rs`.
+// - `anthropic`: Anthropic's Messages API. See `anthropic.rs`.
+//
+// Providers send their requests with a `client::Client`, which holds the options and retries
+// failed requests.
+
+// A future returned by a provider. Trait methods return boxed futures so that providers can be
+// chosen at run time.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// Kind names a provider in the config files and on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Kind {
+    #[default]
+    OpenAi,
+    Azure,
+    Anthropic,
+}
+
+impl Kind {
+    // The environment variable conventionally holding the provider's API key.
+    pub fn api_key_var(self) -> &'static str {
+        match self {
+            Kind::OpenAi => "OPENAI_API_KEY",
+            Kind::Azure => "AZURE_OPENAI_API_KEY",
+            Kind::Anthropic => "ANTHROPIC_API_KEY",
+        }
+    }
+
+    // The base URL of the provider's API, if it has one. Each Azure resource has its own.
+    pub fn default_base_url(self) -> Option<&'static str> {
+        match self {
+            Kind::OpenAi => Some(client::DEFAULT_BASE_URL),
+            Kind::Azure => None,
+            Kind::Anthropic => Some(anthropic::DEFAULT_BASE_URL),
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "openai" => Ok(Kind::OpenAi),
+            "azure" => Ok(Kind::Azure),
+            "anthropic" => Ok(Kind::Anthropic),
+            _ => Err(anyhow!(
+                "invalid provider `{}`: expected `openai`, `azure` or `anthropic`",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Kind {
+    type Error = Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Kind> for String {
+    fn from(kind: Kind) -> Self {
+        kind.to_string()
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::OpenAi => write!(f, "openai"),
+            Kind::Azure => write!(f, "azure"),
+            Kind::Anthropic => write!(f, "anthropic"),
+        }
+    }
+}
+
+// Provider sends chat histories to a particular API. Requests are logged and retried by the
+// client.
+pub trait Provider: Send + Sync {
+    fn kind(&self) -> Kind;
+
+    // The client that sends the requests, holding the options they're sent with.
+    fn client(&self) -> &Client;
+
+    fn client_mut(&mut self) -> &mut Client;
+
+    // Send the chat history, asking for `n` alternative replies, any of which may call the given
+    // tools. Return at least one reply message and the tokens used for all of them.
+    fn send_choices<'a>(
+        &'a self,
+        messages: &'a [api::Message],
+        n: u32,
+        tools: &'a [api::Tool],
+    ) -> BoxFuture<'a, Result<(Vec<api::Message>, api::Usage)>>;
+
+    // Send the chat history, asking for the reply to be streamed back. The reply may call the
+    // given tools. Call `on_delta` with each fragment of the reply's text as it arrives. Return the
+    // assembled reply message and the tokens used, if the API reported them.
+    fn send_streaming<'a>(
+        &'a self,
+        messages: &'a [api::Message],
+        tools: &'a [api::Tool],
+        on_delta: &'a mut (dyn FnMut(&str) + Send),
+    ) -> BoxFuture<'a, Result<(api::Message, Option<api::Usage>)>>;
+
+    // Send the chat history. Return the reply message and the tokens used.
+    fn send<'a>(
+        &'a self,
+        messages: &'a [api::Message],
+    ) -> BoxFuture<'a, Result<(api::Message, api::Usage)>> {
+        Box::pin(async move {
+            let (mut replies, usage) = self.send_choices(messages, 1, &[]).await?;
+            Ok((replies.swap_remove(0), usage))
+        })
+    }
+}
@@ -0,0 +1,327 @@
+use anyhow::Result;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use thousands::Separable;
+
+use crate::commands;
+use crate::input;
+use crate::markdown;
+use chatgpt_api_cli::api;
+use chatgpt_api_cli::attach;
+use chatgpt_api_cli::blocking;
+use chatgpt_api_cli::bot;
+use chatgpt_api_cli::budget;
+use chatgpt_api_cli::client;
+use chatgpt_api_cli::cost;
+use chatgpt_api_cli::retry;
+use chatgpt_api_cli::tools;
+
+// The interactive chat reads the user's input a line at a time. Text is sent to the model, and
+// lines starting with `/` run commands. See `commands.rs`.
+
+// The prompt shown before each line of input.
+const PROMPT: &str = "> ";
+
+// Tell the user that a failed request is about to be retried.
+pub fn print_retry(notice: &retry::Notice) {
+    println!("  [{}]", notice);
+}
+
+// Tell the user that the model is calling a tool.
+pub fn print_tool_call(call: &api::ToolCall) {
+    println!(
+        "  [Calling {} with {}]",
+        call.function.name, call.function.arguments
+    );
+}
+
+// Ask the user whether to send a request that's over a soft limit.
+fn confirm_overrun(input: &Mutex<input::Input>, overrun: &budget::Overrun) -> bool {
+    let question = format!("  [Warning: {}] Send it anyway? [y/N] ", overrun);
+    match input.lock().unwrap().ask(&question) {
+        Ok(answer) => answer.is_some_and(|a| matches!(a.to_lowercase().as_str(), "y" | "yes")),
+        Err(e) => {
+            println!("  [Error: {:#}]", e);
+            false
+        }
+    }
+}
+
+// Ask the user whether the model may make a tool call with side effects.
+fn approve_tool_call(input: &Mutex<input::Input>, call: &api::ToolCall) -> tools::Approval {
+    let question = format!(
+        "  [Allow {} with {}? y/n/always] ",
+        call.function.name, call.function.arguments
+    );
+    loop {
+        match input.lock().unwrap().ask(&question) {
+            Ok(Some(answer)) => match answer.to_lowercase().as_str() {
+                "y" | "yes" => return tools::Approval::Yes,
+                "" | "n" | "no" => return tools::Approval::No,
+                "a" | "always" => return tools::Approval::Always,
+                _ => println!("  [Enter y, n or always]"),
+            },
+            Ok(None) => return tools::Approval::No,
+            Err(e) => {
+                println!("  [Error: {:#}]", e);
+                return tools::Approval::No;
+            }
+        }
+    }
+}
+
+// Show the alternative replies, numbered, and ask the user which to keep in the chat history.
+fn choose_reply(input: &Mutex<input::Input>, render: bool, replies: &[String]) -> usize {
+    for (i, reply) in replies.iter().enumerate() {
+        let mut at_line_start = true;
+        print_now(
+            &format!("GPT ({}/{}): ", i + 1, replies.len()),
+            &mut at_line_start,
+        );
+        if render {
+            let mut renderer = markdown::Renderer::new();
+            print_now(&renderer.push(reply), &mut at_line_start);
+            print_now(&renderer.finish(), &mut at_line_start);
+        } else {
+            print_now(reply, &mut at_line_start);
+        }
+        if !at_line_start {
+            println!();
+        }
+    }
+    let question = format!("  [Keep which reply? 1-{}, default 1] ", replies.len());
+    loop {
+        let answer = match input.lock().unwrap().ask(&question) {
+            Ok(answer) => answer.unwrap_or_default(),
+            Err(e) => {
+                println!("  [Error: {:#}]", e);
+                String::new()
+            }
+        };
+        let chosen = match answer.as_str() {
+            "" => 1,
+            answer => match answer.parse() {
+                Ok(chosen) if (1..=replies.len()).contains(&chosen) => chosen,
+                _ => {
+                    println!("  [Enter a number from 1 to {}]", replies.len());
+                    continue;
+                }
+            },
+        };
+        println!(
+            "  [Kept reply {}; enter `/discarded` to see the others]",
+            chosen
+        );
+        return chosen - 1;
+    }
+}
+
+// Print text right away, noting whether it ended a line.
+fn print_now(text: &str, at_line_start: &mut bool) {
+    if text.is_empty() {
+        return;
+    }
+    print!("{}", text);
+    // A failed flush only delays the text until the next one.
+    let _ = io::stdout().flush();
+    *at_line_start = text.ends_with('\n');
+}
+
+// Send the chat to the API, printing the response as it arrives followed by the tokens used and
+// their cost, or the error that occurred. If `render` is set, the response's Markdown is rendered.
+pub fn print_response<F>(chat_bot: &mut blocking::ChatBot, render: bool, send: F)
+where
+    F: FnOnce(&mut blocking::ChatBot, &mut (dyn FnMut(&str) + Send)) -> Result<bot::Reply>,
+{
+    let mut renderer = render.then(markdown::Renderer::new);
+    let mut started = false;
+    let mut at_line_start = true;
+    let mut print_delta = |delta: &str| {
+        if !started {
+            print_now("GPT: ", &mut at_line_start);
+            started = true;
+        }
+        match &mut renderer {
+            Some(renderer) => print_now(&renderer.push(delta), &mut at_line_start),
+            None => print_now(delta, &mut at_line_start),
+        }
+    };
+    let res = send(chat_bot, &mut print_delta);
+    if let Some(renderer) = &mut renderer {
+        print_now(&renderer.finish(), &mut at_line_start);
+    }
+    if !at_line_start {
+        println!();
+    }
+    match res {
+        Ok(reply) => {
+            if reply.summarized > 0 {
+                println!(
+                    "  [Summarized {} older messages to keep the chat short; enter `/summary` to \
+                     view]",
+                    reply.summarized
+                );
+            }
+            if reply.dropped > 0 {
+                println!(
+                    "  [Left out {} older messages to fit the context window]",
+                    reply.dropped
+                );
+            }
+            if let Some(usage) = reply.usage {
+                let tokens = format!(
+                    "{} prompt + {} completion tokens",
+                    usage.prompt_tokens.separate_with_commas(),
+                    usage.completion_tokens.separate_with_commas()
+                );
+                match reply.cost {
+                    Some(cost) => println!(
+                        "  [{}, {}; {} this session]",
+                        tokens,
+                        cost::format(cost),
+                        cost::format(chat_bot.costs().cost)
+                    ),
+                    None => println!("  [{}; no price is known for {}]", tokens, chat_bot.model()),
+                }
+            }
+        }
+        Err(e) if e.is::<blocking::Cancelled>() => {
+            println!("  [Cancelled; enter `/retry` to resend]");
+        }
+        Err(e) => {
+            println!("  [Error: {}]", e);
+            if let Some(hint) = error_hint(&e) {
+                println!("  [{}]", hint);
+            }
+        }
+    }
+}
+
+// Attach files to the next prompt, showing the tokens each adds.
+pub fn add_attachments(chat_bot: &mut bot::ChatBot, attachments: Vec<attach::Attachment>) {
+    for attachment in attachments {
+        let path = attachment.path.clone();
+        let tokens = chat_bot.attach(attachment);
+        println!(
+            "  [Attached {} ({} tokens)]",
+            path,
+            tokens.separate_with_commas()
+        );
+    }
+}
+
+// Send the user's text to the API, with the files it references as `@path`, and print the response.
+pub fn send_text(chat_bot: &mut blocking::ChatBot, render: bool, text: &str) {
+    for path in attach::references(text) {
+        match attach::read(&path) {
+            Ok(attachments) => add_attachments(chat_bot, attachments),
+            Err(e) => println!("  [Error: {:#}]", e),
+        }
+    }
+    println!(
+        "  [Sending chat to {} (~{} prompt tokens)...]",
+        chat_bot.model(),
+        chat_bot.prompt_tokens(Some(text)).separate_with_commas()
+    );
+    print_response(chat_bot, render, |chat_bot, on_delta| {
+        chat_bot.chat(text, on_delta)
+    });
+}
+
+// Suggest what the user can do about an error from the API.
+fn error_hint(e: &anyhow::Error) -> Option<String> {
+    if e.is::<budget::Overrun>() {
+        return Some(
+            "Enter `/clear` to shorten the chat, `/set max_tokens` to lower the reply limit, or \
+             raise the limit in the config file's `[limits]`."
+                .to_string(),
+        );
+    }
+    let e = e.downcast_ref::<client::Error>()?;
+    let hint = match e {
+        client::Error::Auth { .. } => {
+            "Check the API key in the environment variable or key file, and that it can use this \
+             model."
+                .to_string()
+        }
+        client::Error::RateLimit { .. } => {
+            "Wait a moment and enter `/retry` to resend, or raise `--max-retries`.".to_string()
+        }
+        client::Error::QuotaExceeded { .. } => {
+            "Check the plan and billing details of the API account.".to_string()
+        }
+        client::Error::ContextLengthExceeded { .. } => {
+            "The chat is too long for the model. Enter `/clear` to clear the chat history, or use \
+             a model with a larger context window."
+                .to_string()
+        }
+        client::Error::InvalidRequest { .. } => {
+            match e.api_error().and_then(|a| a.param.as_ref()) {
+                Some(param) => format!(
+                    "Check the `{}` parameter, then enter `/retry` to resend.",
+                    param
+                ),
+                None => {
+                    "Check the model and parameters, then enter `/retry` to resend.".to_string()
+                }
+            }
+        }
+        client::Error::Server { .. } => {
+            "The API had a problem. Enter `/retry` to resend.".to_string()
+        }
+        client::Error::Stream { .. } => {
+            "The API failed partway through the reply. Enter `/retry` to resend.".to_string()
+        }
+        client::Error::Transport(_) => {
+            "Check the network connection and the base URL, then enter `/retry` to resend."
+                .to_string()
+        }
+        client::Error::Response(_) => return None,
+    };
+    Some(hint)
+}
+
+// Chat with the model until the user quits or the input ends.
+pub fn run(chat_bot: &mut blocking::ChatBot, render: bool) -> Result<()> {
+    let registry = Arc::new(commands::Registry::new());
+    let input = Arc::new(Mutex::new(input::Input::new(registry.clone())?));
+    chat_bot.set_confirm(Box::new({
+        let input = input.clone();
+        move |overrun| confirm_overrun(&input, overrun)
+    }));
+    chat_bot.set_choose(Box::new({
+        let input = input.clone();
+        move |replies| choose_reply(&input, render, replies)
+    }));
+    chat_bot.set_approve(Box::new({
+        let input = input.clone();
+        move |call| approve_tool_call(&input, call)
+    }));
+    println!("{}{}", PROMPT, registry.help());
+    loop {
+        // Read a line of input from the user. The end of the input exits, like `/quit`.
+        let Some(line) = input.lock().unwrap().read_line(PROMPT)? else {
+            println!("  [Exiting]");
+            break;
+        };
+
+        if line.is_empty() {
+            println!("{}{}", PROMPT, registry.help());
+        } else if let Some(text) = line.strip_prefix("//") {
+            // A doubled slash sends text that starts with a slash.
+            send_text(chat_bot, render, &format!("/{}", text));
+        } else if line.starts_with('/') {
+            let mut context = commands::Context {
+                chat_bot,
+                render,
+                registry: &registry,
+            };
+            if let commands::Flow::Quit = registry.run(&mut context, &line) {
+                break;
+            }
+        } else {
+            send_text(chat_bot, render, &line);
+        }
+    }
+    Ok(())
+}
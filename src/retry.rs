@@ -0,0 +1,252 @@
+use rand::Rng;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+// Requests that fail with a rate limit, a server error, or a network timeout are retried after a
+// delay. The delay doubles with each retry, with random jitter so that many clients don't retry in
+// lockstep. If the server says how long to wait, its delay is used instead, unless it's longer than
+// the longest delay allowed, when the request fails rather than stall the caller.
+
+pub const DEFAULT_MAX_RETRIES: u32 = 4;
+const DEFAULT_BASE_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+// The fraction of the backoff delay that is randomized. A delay of d becomes a random delay between
+// d * (1 - JITTER) and d.
+const JITTER: f64 = 0.5;
+
+// The headers the API uses to say how long to wait before retrying.
+const RETRY_AFTER_MS: &str = "retry-after-ms";
+const RETRY_AFTER: &str = "retry-after";
+const RATELIMIT_RESET_HEADERS: [&str; 2] =
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"];
+
+// Policy sets how many times and how quickly failed requests are retried.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    // The number of retries after the first attempt. Zero disables retrying.
+    pub max_retries: u32,
+    // The delay before the first retry, before jitter.
+    pub base_delay: Duration,
+    // The longest delay between attempts. A request the server asks to wait longer for fails
+    // instead.
+    pub max_delay: Duration,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: DEFAULT_BASE_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+        }
+    }
+}
+
+impl Policy {
+    // The jittered exponential backoff delay before the given retry, counting from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        delay.mul_f64(1.0 - JITTER * rand::thread_rng().gen::<f64>())
+    }
+}
+
+// Notice describes a retry that's about to happen, so that the user can be told.
+#[derive(Debug)]
+pub struct Notice {
+    // How long until the next attempt.
+    pub delay: Duration,
+    // The number of the next attempt, counting from 1.
+    pub attempt: u32,
+    // The total number of attempts that will be made.
+    pub max_attempts: u32,
+    // What went wrong with the previous attempt.
+    pub reason: String,
+}
+
+impl fmt::Display for Notice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}; retrying in {:.0}s (attempt {}/{})",
+            self.reason,
+            self.delay.as_secs_f64().ceil(),
+            self.attempt,
+            self.max_attempts
+        )
+    }
+}
+
+// Notifier is called with a notice before each retry. It may be called from any thread that runs
+// requests.
+pub type Notifier = Box<dyn Fn(&Notice) + Send + Sync>;
+
+// The status Anthropic's API responds with when it's temporarily overloaded.
+const OVERLOADED: u16 = 529;
+
+// Whether a response with the given status is worth retrying.
+pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+    ) || status.as_u16() == OVERLOADED
+}
+
+// Whether a request that failed without a response is worth retrying.
+pub(crate) fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect()
+}
+
+// The delay the server asked for before retrying a response with the given status, if any.
+// `Retry-After-Ms` and `Retry-After` are checked first. Otherwise, for a rate limit, the latest of
+// the rate limit reset times is used. Other responses carry them too, but then they describe quota
+// windows unrelated to the failure.
+pub(crate) fn server_delay(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(ms) = header(RETRY_AFTER_MS).and_then(|v| v.trim().parse::<f64>().ok()) {
+        return Duration::try_from_secs_f64(ms / 1000.0).ok();
+    }
+    if let Some(value) = header(RETRY_AFTER) {
+        // The value is either a number of seconds or an HTTP date.
+        let value = value.trim();
+        if let Ok(secs) = value.parse::<f64>() {
+            return Duration::try_from_secs_f64(secs).ok();
+        }
+        if let Ok(date) = httpdate::parse_http_date(value) {
+            return Some(
+                date.duration_since(SystemTime::now())
+                    .unwrap_or(Duration::ZERO),
+            );
+        }
+    }
+    if status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    RATELIMIT_RESET_HEADERS
+        .iter()
+        .filter_map(|name| header(name).and_then(parse_reset_duration))
+        .max()
+}
+
+// Parse a rate limit reset duration such as `20ms`, `1s`, `6m0s` or `1h2m3.5s`.
+fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += number * scale;
+        rest = &rest[unit_len..];
+    }
+    Duration::try_from_secs_f64(total).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderName, HeaderValue};
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_str(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn delay(status: StatusCode, pairs: &[(&'static str, &str)]) -> Option<Duration> {
+        server_delay(status, &headers(pairs))
+    }
+
+    #[test]
+    fn retry_after_is_read_in_seconds_or_milliseconds() {
+        let status = StatusCode::SERVICE_UNAVAILABLE;
+        assert_eq!(
+            delay(status, &[("retry-after", "2")]),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            delay(status, &[("retry-after", "0.5")]),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            delay(status, &[("retry-after-ms", "250"), ("retry-after", "2")]),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(delay(status, &[("retry-after", "-1")]), None);
+        assert_eq!(delay(status, &[("retry-after", "soon")]), None);
+        assert_eq!(delay(status, &[]), None);
+    }
+
+    #[test]
+    fn retry_after_dates_are_read_relative_to_now() {
+        let status = StatusCode::TOO_MANY_REQUESTS;
+        let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        let wait = delay(status, &[("retry-after", &later)]).unwrap();
+        assert!(wait > Duration::from_secs(25) && wait <= Duration::from_secs(30));
+        let earlier = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(30));
+        assert_eq!(
+            delay(status, &[("retry-after", &earlier)]),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn rate_limits_wait_for_the_latest_reset() {
+        let reset = [
+            ("x-ratelimit-reset-requests", "1s"),
+            ("x-ratelimit-reset-tokens", "6m0s"),
+        ];
+        assert_eq!(
+            delay(StatusCode::TOO_MANY_REQUESTS, &reset),
+            Some(Duration::from_secs(360))
+        );
+        // The reset times describe quota windows unrelated to other failures.
+        assert_eq!(delay(StatusCode::INTERNAL_SERVER_ERROR, &reset), None);
+    }
+
+    #[test]
+    fn reset_durations() {
+        assert_eq!(
+            parse_reset_duration("20ms"),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(
+            parse_reset_duration("1h2m3.5s"),
+            Some(Duration::from_millis(3_723_500))
+        );
+        assert_eq!(parse_reset_duration(""), None);
+        assert_eq!(parse_reset_duration("1d"), None);
+        assert_eq!(parse_reset_duration("s"), None);
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::api;
+use crate::client;
+use crate::provider;
+
+// Saved sessions are JSON files named `<name>.json` in the `chatgpt_api_cli/sessions` directory of
+// the user's data directory (e.g. `~/.local/share/chatgpt_api_cli/sessions`).
+const SESSIONS_DIR: &str = "sessions";
+const SESSION_EXTENSION: &str = "json";
+
+// The version of the session file format. Increment it when the format changes incompatibly.
+const SESSION_VERSION: u32 = 1;
+
+// Session is a saved conversation: the chat history plus the model and parameters it was held
+// with, so that it can be continued later.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    // The provider the chat was held with. Sessions saved before there were other providers were
+    // held with OpenAI's.
+    #[serde(default)]
+    pub provider: provider::Kind,
+    pub options: client::Options,
+    pub messages: Vec<api::Message>,
+    // The indices of the pinned messages. Optional so that older session files still load.
+    #[serde(default)]
+    pub pinned: Vec<usize>,
+    // The alternative replies that weren't kept in the chat history. Optional so that older session
+    // files still load.
+    #[serde(default)]
+    pub discarded: Vec<Discarded>,
+}
+
+impl Session {
+    pub fn new(
+        provider: provider::Kind,
+        options: client::Options,
+        messages: Vec<api::Message>,
+        pinned: Vec<usize>,
+        discarded: Vec<Discarded>,
+    ) -> Self {
+        Self {
+            version: SESSION_VERSION,
+            provider,
+            options,
+            messages,
+            pinned,
+            discarded,
+        }
+    }
+}
+
+// Discarded is an alternative reply that the user chose not to keep in the chat history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Discarded {
+    // The user's message that it replied to.
+    pub prompt: String,
+    pub reply: String,
+}
+
+// The directory holding the saved sessions.
+fn sessions_dir() -> Result<PathBuf> {
+    let dir = dirs::data_dir().ok_or(anyhow!("couldn't find the user's data directory"))?;
+    Ok(dir.join(crate::APP_DIR).join(SESSIONS_DIR))
+}
+
+// The file holding the named session. Names are restricted so that they can't escape the sessions
+// directory.
+fn session_path(name: &str) -> Result<PathBuf> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_.".contains(c))
+    {
+        bail!(
+            "invalid session name `{}`: use letters, digits, `-`, `_` and `.`",
+            name
+        );
+    }
+    Ok(sessions_dir()?.join(format!("{}.{}", name, SESSION_EXTENSION)))
+}
+
+// Save the session under the given name, replacing any session saved with that name. Return the
+// path of the file written.
+pub fn save(name: &str, session: &Session) -> Result<PathBuf> {
+    let path = session_path(name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(format!(
+            "error creating sessions directory: {}",
+            dir.display()
+        ))?;
+    }
+    let json = serde_json::to_string_pretty(session)?;
+    fs::write(&path, json).context(format!("error writing session file: {}", path.display()))?;
+    Ok(path)
+}
+
+// Load the session saved under the given name.
+pub fn load(name: &str) -> Result<Session> {
+    let path = session_path(name)?;
+    let json = fs::read_to_string(&path)
+        .context(format!("error reading session file: {}", path.display()))?;
+    let session: Session = serde_json::from_str(&json)
+        .context(format!("error parsing session file: {}", path.display()))?;
+    if session.version > SESSION_VERSION {
+        bail!(
+            "session file {} has version {}, but only versions up to {} are supported",
+            path.display(),
+            session.version,
+            SESSION_VERSION
+        );
+    }
+    Ok(session)
+}
+
+// The names and paths of the saved sessions.
+fn session_files() -> Result<Vec<(String, PathBuf)>> {
+    let dir = sessions_dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => {
+            return Err(e).context(format!(
+                "error reading sessions directory: {}",
+                dir.display()
+            ))
+        }
+    };
+
+    let mut files = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(SESSION_EXTENSION) {
+            continue;
+        }
+        if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+            files.push((name.to_string(), path.clone()));
+        }
+    }
+    Ok(files)
+}
+
+// The names of the saved sessions, sorted.
+pub fn list() -> Result<Vec<String>> {
+    let mut names: Vec<String> = session_files()?.into_iter().map(|(name, _)| name).collect();
+    names.sort();
+    Ok(names)
+}
+
+// The name of the most recently saved session, if there are any.
+pub fn latest() -> Result<Option<String>> {
+    let mut latest: Option<(SystemTime, String)> = None;
+    for (name, path) in session_files()? {
+        let Ok(modified) = path.metadata().and_then(|m| m.modified()) else {
+            continue;
+        };
+        if latest.as_ref().is_none_or(|(time, _)| modified > *time) {
+            latest = Some((modified, name));
+        }
+    }
+    Ok(latest.map(|(_, name)| name))
+}
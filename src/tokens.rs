@@ -0,0 +1,99 @@
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::{CoreBPE, Rank};
+
+use crate::api;
+use crate::image;
+
+// Token counting happens locally, without a network round trip, using the same byte-pair encodings
+// as the API. The vocabularies for the cl100k and o200k encodings are compiled into the binary, so
+// counting works offline.
+//
+// Counts of chat messages include the tokens the API adds to frame each message, following
+// https://cookbook.openai.com/examples/how_to_count_tokens_with_tiktoken.
+// The API may change this framing between models, so treat the counts as close estimates.
+
+// Each message is framed as `<|start|>{role}\n{content}<|end|>\n`.
+const TOKENS_PER_MESSAGE: usize = 3;
+// Each reply is primed with `<|start|>assistant<|message|>`.
+const TOKENS_PER_REPLY: usize = 3;
+
+// Counter counts tokens as a particular model would.
+pub struct Counter {
+    bpe: &'static CoreBPE,
+}
+
+impl Counter {
+    // Create a counter for the named model. Models without a known encoding, such as those served
+    // by other OpenAI-compatible servers, are counted with cl100k as an estimate.
+    pub fn for_model(model: &str) -> Self {
+        let bpe = match get_tokenizer(model) {
+            Some(Tokenizer::O200kBase) => tiktoken_rs::o200k_base_singleton(),
+            _ => tiktoken_rs::cl100k_base_singleton(),
+        };
+        Self { bpe }
+    }
+
+    // The number of tokens in the text.
+    pub fn text(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+
+    // The tokens of the text.
+    pub fn encode(&self, text: &str) -> Vec<Rank> {
+        self.bpe.encode_with_special_tokens(text)
+    }
+
+    // The text of the first `max_tokens` of the tokens.
+    pub fn decode_prefix(&self, tokens: &[Rank], max_tokens: usize) -> String {
+        // The tokens encode the text's bytes in order, so the first ones decode to the start of the
+        // text, unless the cut falls within a character that spans tokens. Then the cut is moved
+        // back to the start of the character, at most a few tokens earlier.
+        (0..=max_tokens.min(tokens.len()))
+            .rev()
+            .find_map(|n| self.bpe.decode(tokens[..n].to_vec()).ok())
+            .unwrap_or_default()
+    }
+
+    // The text cut to its first `max_tokens` tokens, or None if it's already no longer than that.
+    pub fn truncate(&self, text: &str, max_tokens: usize) -> Option<String> {
+        let tokens = self.encode(text);
+        (tokens.len() > max_tokens).then(|| self.decode_prefix(&tokens, max_tokens))
+    }
+
+    // The number of tokens in a single message, including its framing. The tool calls of a
+    // message are counted by their names and arguments, which is an underestimate.
+    pub fn message(&self, message: &api::Message) -> usize {
+        TOKENS_PER_MESSAGE
+            + self.text(&message.role)
+            + message.content.as_ref().map_or(0, |c| self.content(c))
+            + message
+                .tool_calls
+                .iter()
+                .flatten()
+                .map(|call| self.text(&call.function.name) + self.text(&call.function.arguments))
+                .sum::<usize>()
+    }
+
+    // The number of tokens in a message's content. Images are estimated from their size. See
+    // `image.rs`.
+    fn content(&self, content: &api::Content) -> usize {
+        match content {
+            api::Content::Text(text) => self.text(text),
+            api::Content::Parts(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    api::ContentPart::Text { text } => self.text(text),
+                    api::ContentPart::ImageUrl { image_url } => {
+                        image::tokens(&image_url.url, image_url.detail.as_deref())
+                    }
+                })
+                .sum(),
+        }
+    }
+
+    // The number of prompt tokens a request with the given messages will use, including the tokens
+    // that prime the reply.
+    pub fn messages(&self, messages: &[api::Message]) -> usize {
+        messages.iter().map(|m| self.message(m)).sum::<usize>() + TOKENS_PER_REPLY
+    }
+}
@@ -0,0 +1,610 @@
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use thousands::Separable;
+
+use crate::api;
+use crate::attach;
+use crate::tokens;
+
+// Tools are functions that the model can call while answering. Each request lists the tools'
+// names, descriptions and parameters, and the model may reply with calls of one or more of them
+// instead of an answer. The calls are executed, their results are sent back as 'tool' messages, and
+// the model continues until it gives a final answer. See `ChatBot::send`.
+//
+// The built-in tools let the model read files, list directories, search files and run shell
+// commands. They're offered when started with `--tools`, or with `tools = true` in the config file.
+// Paths are confined to the allowed directory, `tools_dir`, which is the current directory unless
+// configured otherwise, and the output of each call is cut to `tool_output_tokens` tokens. Calls
+// with side effects, such as shell commands, are shown to the user to approve first. A shell
+// command runs in the allowed directory, but nothing stops it from reaching outside it, so read it
+// before approving it.
+
+// The tokens of output returned to the model from each call, unless configured otherwise.
+pub const DEFAULT_OUTPUT_TOKENS: usize = 2_000;
+
+// The most matching lines returned by a search.
+const MAX_GREP_MATCHES: usize = 200;
+
+// The largest file searched, in bytes. Larger files are more likely data or build output than
+// anything worth a match.
+const MAX_GREP_FILE_BYTES: u64 = 1024 * 1024;
+
+// The bytes read from a file per token of output allowed, so that only as much of a large file is
+// read as could be returned. Few tokens are longer.
+const READ_BYTES_PER_TOKEN: usize = 16;
+
+// How long a shell command may run before it's killed.
+const SHELL_TIMEOUT: Duration = Duration::from_secs(60);
+
+// How long to wait for the rest of a killed command's output. A process that left the command's
+// process group may hold its pipes open indefinitely.
+const KILLED_OUTPUT_WAIT: Duration = Duration::from_secs(1);
+
+// The number of bytes at the start of a file checked for NUL bytes to tell whether it's binary.
+const BINARY_CHECK_BYTES: usize = 8_000;
+
+// Tool is a function that the model can call.
+pub trait Tool: Send + Sync {
+    // The name the model calls the tool by.
+    fn name(&self) -> &'static str;
+    // What the tool does and when to use it, for the model.
+    fn description(&self) -> &'static str;
+    // The parameters the tool takes, as a JSON Schema object.
+    fn parameters(&self) -> Value;
+    // Whether calls have side effects, and so must be approved by the user before they run.
+    fn needs_approval(&self) -> bool {
+        false
+    }
+    // Run the tool with the arguments given by the model and return the result for the model.
+    // Calls run on a thread where blocking is allowed. A call that takes long should stop once
+    // `cancel` says its result is no longer wanted.
+    fn call(&self, arguments: &Value, cancel: &Cancel) -> Result<String>;
+}
+
+// Cancel tells a running tool call that its result is no longer wanted, as when the request it's
+// part of has been cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+// CancelOnDrop cancels a tool call when the future waiting for it is dropped.
+struct CancelOnDrop(Cancel);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+// Notifier is called with each tool call before it's executed, so that the user can be told.
+pub type Notifier = Box<dyn Fn(&api::ToolCall) + Send + Sync>;
+
+// Approval is the user's answer when asked whether a tool call may run.
+pub enum Approval {
+    Yes,
+    No,
+    // Run this call and any later calls of the same tool without asking.
+    Always,
+}
+
+// Approve is asked whether a tool call with side effects may run. Without it, such calls are
+// refused.
+pub type Approve = Box<dyn Fn(&api::ToolCall) -> Approval + Send + Sync>;
+
+// Registry holds the tools offered to the model and executes its calls of them.
+pub struct Registry {
+    tools: Vec<Arc<dyn Tool>>,
+    notifier: Option<Notifier>,
+    approve: Option<Approve>,
+    // The tools that the user has allowed to run without asking.
+    always_approved: HashSet<&'static str>,
+}
+
+impl Registry {
+    pub fn new(tools: Vec<Box<dyn Tool>>) -> Self {
+        Self {
+            tools: tools.into_iter().map(Arc::from).collect(),
+            notifier: None,
+            approve: None,
+            always_approved: HashSet::new(),
+        }
+    }
+
+    // Set the function called before each tool call is executed.
+    pub fn set_notifier(&mut self, notifier: Notifier) {
+        self.notifier = Some(notifier);
+    }
+
+    // Set the function asked whether a tool call with side effects may run.
+    pub fn set_approve(&mut self, approve: Approve) {
+        self.approve = Some(approve);
+    }
+
+    // The definitions of the tools, as sent with each request.
+    pub fn definitions(&self) -> Vec<api::Tool> {
+        self.tools
+            .iter()
+            .map(|tool| api::Tool {
+                kind: "function".to_string(),
+                function: api::FunctionDefinition {
+                    name: tool.name().to_string(),
+                    description: tool.description().to_string(),
+                    parameters: tool.parameters(),
+                },
+            })
+            .collect()
+    }
+
+    // Execute the model's call of a tool and return the message giving its result. A failure is
+    // given as the result so that the model can correct its call or carry on without it.
+    pub async fn call(&mut self, call: &api::ToolCall) -> api::Message {
+        let content = match self.run(call).await {
+            Ok(content) => content,
+            Err(e) => format!("Error: {:#}", e),
+        };
+        api::Message::tool_result(&call.id, content)
+    }
+
+    // Execute the call, once approved if the tool needs it.
+    async fn run(&mut self, call: &api::ToolCall) -> Result<String> {
+        let tool = self
+            .tools
+            .iter()
+            .find(|tool| tool.name() == call.function.name)
+            .ok_or_else(|| anyhow!("unknown tool `{}`", call.function.name))?
+            .clone();
+        if tool.needs_approval() && !self.always_approved.contains(tool.name()) {
+            let approval = match &self.approve {
+                Some(approve) => approve(call),
+                None => Approval::No,
+            };
+            match approval {
+                Approval::Yes => {}
+                Approval::Always => {
+                    self.always_approved.insert(tool.name());
+                }
+                Approval::No => bail!("the user didn't allow this call"),
+            }
+        } else if let Some(notify) = &self.notifier {
+            notify(call);
+        }
+        // A call of a tool without parameters may have no arguments at all.
+        let arguments = match call.function.arguments.trim() {
+            "" => Value::Object(Default::default()),
+            arguments => {
+                serde_json::from_str(arguments).context("the arguments aren't valid JSON")?
+            }
+        };
+        // Tools read files and run commands synchronously, so they're kept off the async threads.
+        // The thread can't be stopped, so dropping this future tells the tool to stop instead.
+        let cancel = Cancel::default();
+        let _cancel_on_drop = CancelOnDrop(cancel.clone());
+        tokio::task::spawn_blocking(move || tool.call(&arguments, &cancel))
+            .await
+            .context("the tool failed")?
+    }
+}
+
+// Workspace is the directory the built-in tools are confined to, and the limit on their output.
+pub struct Workspace {
+    // The allowed directory, canonicalized so that paths can be checked against it.
+    root: PathBuf,
+    output_tokens: usize,
+    counter: tokens::Counter,
+}
+
+impl Workspace {
+    pub fn new(root: &Path, output_tokens: usize) -> Result<Self> {
+        let root = root
+            .canonicalize()
+            .context(format!("error finding tools directory: {}", root.display()))?;
+        if !root.is_dir() {
+            bail!("the tools directory isn't a directory: {}", root.display());
+        }
+        Ok(Self {
+            root,
+            output_tokens,
+            counter: tokens::Counter::for_model(""),
+        })
+    }
+
+    // Resolve a path given by the model, relative to the allowed directory. Paths outside it,
+    // including through symbolic links, are refused.
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let resolved = self
+            .root
+            .join(path)
+            .canonicalize()
+            .context(format!("can't find `{}`", path))?;
+        if !resolved.starts_with(&self.root) {
+            bail!("`{}` is outside the allowed directory", path);
+        }
+        Ok(resolved)
+    }
+
+    // The path relative to the allowed directory, for showing to the model.
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) if relative.as_os_str().is_empty() => Path::new("."),
+            Ok(relative) => relative,
+            Err(_) => path,
+        }
+    }
+
+    // The output cut to the token limit, with a note of how much was left out.
+    fn limit(&self, output: String) -> String {
+        let tokens = self.counter.encode(&output);
+        if tokens.len() <= self.output_tokens {
+            return output;
+        }
+        format!(
+            "{}\n[Output truncated to {} of {} tokens]",
+            self.counter.decode_prefix(&tokens, self.output_tokens),
+            self.output_tokens.separate_with_commas(),
+            tokens.len().separate_with_commas()
+        )
+    }
+}
+
+// Whether the contents look like those of a binary file rather than text.
+pub(crate) fn is_binary(contents: &[u8]) -> bool {
+    contents[..contents.len().min(BINARY_CHECK_BYTES)].contains(&0)
+}
+
+// The string argument with the given name, or the default if given and the argument is missing.
+fn string_arg<'a>(arguments: &'a Value, name: &str, default: Option<&'a str>) -> Result<&'a str> {
+    match arguments.get(name) {
+        Some(Value::String(s)) => Ok(s),
+        Some(_) => bail!("`{}` must be a string", name),
+        None => default.ok_or_else(|| anyhow!("missing argument `{}`", name)),
+    }
+}
+
+// The built-in tools, confined to the workspace.
+pub fn builtin(workspace: Workspace) -> Vec<Box<dyn Tool>> {
+    let workspace = Arc::new(workspace);
+    vec![
+        Box::new(ReadFile(workspace.clone())),
+        Box::new(ListDirectory(workspace.clone())),
+        Box::new(Grep(workspace.clone())),
+        Box::new(RunShellCommand(workspace)),
+    ]
+}
+
+struct ReadFile(Arc<Workspace>);
+
+impl Tool for ReadFile {
+    fn name(&self) -> &'static str {
+        "read_file"
+    }
+    fn description(&self) -> &'static str {
+        "Read a text file in the working directory."
+    }
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "The file's path, relative to the working directory."
+                }
+            },
+            "required": ["path"]
+        })
+    }
+    fn call(&self, arguments: &Value, _cancel: &Cancel) -> Result<String> {
+        let path = self.0.resolve(string_arg(arguments, "path", None)?)?;
+        let relative = self.0.relative(&path);
+        let read = || -> std::io::Result<(Vec<u8>, u64)> {
+            let file = fs::File::open(&path)?;
+            let size = file.metadata()?.len();
+            let mut contents = vec![];
+            let max_bytes = self.0.output_tokens * READ_BYTES_PER_TOKEN;
+            file.take(max_bytes as u64).read_to_end(&mut contents)?;
+            Ok((contents, size))
+        };
+        let (contents, size) = read().context(format!("error reading {}", relative.display()))?;
+        if is_binary(&contents) {
+            bail!("{} is a binary file", relative.display());
+        }
+        let text = String::from_utf8_lossy(&contents);
+        if contents.len() as u64 == size {
+            return Ok(self.0.limit(text.into_owned()));
+        }
+        // Only the start of the file was read, so its length is given in bytes. The read may have
+        // stopped within a character.
+        let tokens = self.0.counter.encode(text.trim_end_matches('\u{FFFD}'));
+        let shown = tokens.len().min(self.0.output_tokens);
+        Ok(format!(
+            "{}\n[Output truncated to the first {} tokens of {} bytes]",
+            self.0.counter.decode_prefix(&tokens, shown),
+            shown.separate_with_commas(),
+            size.separate_with_commas()
+        ))
+    }
+}
+
+struct ListDirectory(Arc<Workspace>);
+
+impl Tool for ListDirectory {
+    fn name(&self) -> &'static str {
+        "list_directory"
+    }
+    fn description(&self) -> &'static str {
+        "List the files and subdirectories of a directory in the working directory. \
+         Subdirectories end with `/`."
+    }
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "The directory's path, relative to the working directory. \
+                        Defaults to the working directory."
+                }
+            }
+        })
+    }
+    fn call(&self, arguments: &Value, _cancel: &Cancel) -> Result<String> {
+        let path = self.0.resolve(string_arg(arguments, "path", Some("."))?)?;
+        let mut entries = vec![];
+        for entry in fs::read_dir(&path).context(format!(
+            "error listing {}",
+            self.0.relative(&path).display()
+        ))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            entries.push(match entry.file_type()?.is_dir() {
+                true => format!("{}/", name),
+                false => name,
+            });
+        }
+        entries.sort();
+        Ok(self.0.limit(entries.join("\n")))
+    }
+}
+
+struct Grep(Arc<Workspace>);
+
+impl Tool for Grep {
+    fn name(&self) -> &'static str {
+        "grep"
+    }
+    fn description(&self) -> &'static str {
+        "Search the text files in a directory of the working directory, and its subdirectories, \
+         for lines matching a regular expression. Hidden files and directories, files ignored by \
+         Git, binary files and files over 1 MB are skipped. Returns the matching lines as \
+         `path:line number:text`."
+    }
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "pattern": {
+                    "type": "string",
+                    "description": "The regular expression, in Rust regex syntax."
+                },
+                "path": {
+                    "type": "string",
+                    "description": "The file or directory to search, relative to the working \
+                        directory. Defaults to the working directory."
+                }
+            },
+            "required": ["pattern"]
+        })
+    }
+    fn call(&self, arguments: &Value, cancel: &Cancel) -> Result<String> {
+        let pattern = string_arg(arguments, "pattern", None)?;
+        let regex = Regex::new(pattern).context("invalid regular expression")?;
+        let path = self.0.resolve(string_arg(arguments, "path", Some("."))?)?;
+
+        let mut matches = vec![];
+        // The search stops once the matches would be cut by the output limit anyway.
+        let mut tokens = 0;
+        let mut ignore = attach::Ignore::new(&path);
+        let files = walkdir::WalkDir::new(&path)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| {
+                e.depth() == 0
+                    || !e.file_name().to_string_lossy().starts_with('.')
+                        && !ignore.is_ignored(e.path(), e.file_type().is_dir())
+            })
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter(|e| e.metadata().is_ok_and(|m| m.len() <= MAX_GREP_FILE_BYTES));
+        'files: for file in files {
+            if cancel.is_cancelled() {
+                bail!("cancelled");
+            }
+            // Unreadable and binary files aren't worth failing the search over.
+            let Ok(contents) = fs::read(file.path()) else {
+                continue;
+            };
+            if is_binary(&contents) {
+                continue;
+            }
+            let text = String::from_utf8_lossy(&contents);
+            for (i, line) in text.lines().enumerate() {
+                if !regex.is_match(line) {
+                    continue;
+                }
+                if matches.len() == MAX_GREP_MATCHES {
+                    matches.push(format!("[Stopped after {} matches]", MAX_GREP_MATCHES));
+                    break 'files;
+                }
+                if tokens > self.0.output_tokens {
+                    break 'files;
+                }
+                let found = format!(
+                    "{}:{}:{}",
+                    self.0.relative(file.path()).display(),
+                    i + 1,
+                    line
+                );
+                tokens += self.0.counter.text(&found);
+                matches.push(found);
+            }
+        }
+        if matches.is_empty() {
+            return Ok("No matches.".to_string());
+        }
+        Ok(self.0.limit(matches.join("\n")))
+    }
+}
+
+struct RunShellCommand(Arc<Workspace>);
+
+impl Tool for RunShellCommand {
+    fn name(&self) -> &'static str {
+        "run_shell_command"
+    }
+    fn description(&self) -> &'static str {
+        "Run a command with `sh -c` in the working directory, once the user approves it. Returns \
+         its exit status and output. Commands are killed after 60 seconds, with any processes \
+         they started, and can't read input."
+    }
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "command": {"type": "string", "description": "The shell command."}
+            },
+            "required": ["command"]
+        })
+    }
+    fn needs_approval(&self) -> bool {
+        true
+    }
+    fn call(&self, arguments: &Value, cancel: &Cancel) -> Result<String> {
+        let command = string_arg(arguments, "command", None)?;
+        let mut shell = Command::new("sh");
+        shell
+            .arg("-c")
+            .arg(command)
+            .current_dir(&self.0.root)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // The command runs in a process group of its own so that the processes it starts can be
+        // killed with it.
+        #[cfg(unix)]
+        shell.process_group(0);
+        let mut child = shell.spawn().context("error starting the shell")?;
+
+        let stdout = Output::read(child.stdout.take().map(|p| Box::new(p) as _));
+        let stderr = Output::read(child.stderr.take().map(|p| Box::new(p) as _));
+
+        // Wait for the command and for the end of its output, which the processes it left running
+        // in the background may hold open after it exits. If the call is cancelled, the command
+        // is killed at once.
+        let deadline = Instant::now() + SHELL_TIMEOUT;
+        let mut exit_status = None;
+        let killed = loop {
+            if exit_status.is_none() {
+                exit_status = child.try_wait()?;
+            }
+            if exit_status.is_some() && stdout.is_finished() && stderr.is_finished() {
+                break false;
+            }
+            if Instant::now() >= deadline || cancel.is_cancelled() {
+                kill(&mut child);
+                let _ = child.wait();
+                break true;
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+        if cancel.is_cancelled() {
+            bail!("cancelled");
+        }
+        if killed {
+            let deadline = Instant::now() + KILLED_OUTPUT_WAIT;
+            while !(stdout.is_finished() && stderr.is_finished()) && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+        let status = match (exit_status, killed) {
+            (Some(status), false) => status.to_string(),
+            (Some(status), true) => format!(
+                "{}, and its background processes were killed after {} seconds",
+                status,
+                SHELL_TIMEOUT.as_secs()
+            ),
+            (None, _) => format!("killed after {} seconds", SHELL_TIMEOUT.as_secs()),
+        };
+        let (stdout, stderr) = (stdout.text(), stderr.text());
+        Ok(self.0.limit(format!(
+            "{}\nstdout:\n{}\nstderr:\n{}",
+            status, stdout, stderr
+        )))
+    }
+}
+
+// Output collects what a command writes to one of its pipes, reading it on a thread of its own as
+// it's written so that the command can't block on a full pipe. What's been read so far is kept if
+// the reader is abandoned.
+struct Output {
+    text: Arc<Mutex<Vec<u8>>>,
+    reader: thread::JoinHandle<()>,
+}
+
+impl Output {
+    fn read(pipe: Option<Box<dyn Read + Send>>) -> Self {
+        let text = Arc::new(Mutex::new(vec![]));
+        let read = text.clone();
+        let reader = thread::spawn(move || {
+            let Some(mut pipe) = pipe else { return };
+            let mut buf = [0; 8192];
+            while let Ok(n @ 1..) = pipe.read(&mut buf) {
+                read.lock().unwrap().extend_from_slice(&buf[..n]);
+            }
+        });
+        Self { text, reader }
+    }
+
+    // Whether the pipe has been read to its end.
+    fn is_finished(&self) -> bool {
+        self.reader.is_finished()
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.text.lock().unwrap()).into_owned()
+    }
+}
+
+// Kill the command and the processes it started.
+#[cfg(unix)]
+fn kill(child: &mut Child) {
+    // SAFETY: `kill` has no memory-safety requirements. The negated ID names the process group that
+    // the command was started in.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+}